@@ -1,56 +1,275 @@
-use rkvm_input::writer::Writer;
+use crate::config::Server;
+use rand::Rng;
+use rkvm_input::writer::{DeviceRequest, Writer, WriterBuilder};
 use rkvm_net::auth::{AuthChallenge, AuthStatus};
+#[cfg(feature = "discovery")]
+use rkvm_net::discovery;
 use rkvm_net::message::Message;
 use rkvm_net::version::Version;
-use rkvm_net::{Pong, Update};
+use rkvm_net::{Feedback, Update};
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::future;
 use std::io;
-use std::time::Instant;
+use std::task::Poll;
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use tokio::io::{AsyncWriteExt, BufStream};
+use tokio::io::{AsyncRead, AsyncWriteExt, BufStream};
 use tokio::net::TcpStream;
 use tokio::time;
+use tokio_rustls::client::TlsStream;
 use tokio_rustls::rustls::ServerName;
 use tokio_rustls::TlsConnector;
 
 #[derive(Error, Debug)]
 pub enum Error {
+    #[error("Connection error: {0}")]
+    Connect(io::Error),
     #[error("Network error: {0}")]
     Network(io::Error),
     #[error("Input error: {0}")]
     Input(io::Error),
-    #[error("Incompatible server version (got {server}, expected {client})")]
+    #[error("Incompatible server version, server speaks {server}, client speaks {client}")]
     Version { server: Version, client: Version },
     #[error("Invalid password")]
     Auth,
 }
 
+// Failures after the TCP connection is up usually don't fix themselves (an expired certificate, a wrong password,
+// an incompatible server), so there is a limit on how many of them in a row are retried.
+const MAX_HANDSHAKE_FAILURES: u32 = 5;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+// Failed reconnection attempts are logged at most this often.
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+// Connections dropped sooner than this count as failed attempts, so that a server closing them right away isn't
+// reconnected to in a tight loop.
+const MIN_UPTIME: Duration = Duration::from_secs(10);
+
+// Without a server, one is looked for on the local network every time the client (re)connects. Without a maximum
+// reconnection delay, the first disconnection is returned as an error.
 pub async fn run(
-    hostname: &ServerName,
-    port: u16,
+    server: Option<&Server>,
     connector: TlsConnector,
     password: &str,
+    max_delay: Option<Duration>,
 ) -> Result<(), Error> {
-    // Intentionally don't impose any timeout for TCP connect.
+    let max_delay = match max_delay {
+        Some(max_delay) => max_delay,
+        None => {
+            let stream = connect(server, &connector).await?;
+            let stream = handshake(stream, password).await?;
+
+            return serve(stream).await;
+        }
+    };
+
+    let mut delay = RECONNECT_DELAY;
+    let mut handshake_failures = 0;
+    let mut attempts = 0;
+    let mut last_report: Option<Instant> = None;
+
+    loop {
+        let result = match connect(server, &connector).await {
+            Ok(stream) => handshake(stream, password).await,
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(stream) => {
+                handshake_failures = 0;
+
+                let connected = Instant::now();
+                let result = serve(stream).await;
+
+                // The writers are gone by now, so after a connection that lasted reconnect right away
+                // and only back off if that fails.
+                let stable = connected.elapsed() >= MIN_UPTIME;
+                let report = match stable {
+                    true => {
+                        delay = RECONNECT_DELAY;
+                        attempts = 0;
+                        last_report = None;
+                        true
+                    }
+                    false => {
+                        attempts += 1;
+                        should_report(&mut last_report)
+                    }
+                };
+
+                match result {
+                    // Nothing to do with the connection, reconnecting won't help.
+                    Err(err @ Error::Input(_)) => return Err(err),
+                    Ok(()) if report => tracing::info!("Disconnected"),
+                    Ok(()) => tracing::debug!(attempts = %attempts, "Disconnected"),
+                    Err(err) if report => tracing::error!("Disconnected: {}", err),
+                    Err(err) => tracing::debug!(attempts = %attempts, "Disconnected: {}", err),
+                }
+
+                if stable {
+                    continue;
+                }
+            }
+            Err(Error::Connect(err)) => {
+                attempts += 1;
+
+                match should_report(&mut last_report) {
+                    true => {
+                        tracing::warn!(attempts = %attempts, "Failed to connect: {}, retrying", err)
+                    }
+                    false => tracing::debug!(attempts = %attempts, "Failed to connect: {}", err),
+                }
+            }
+            // Got as far as the server, but not past the handshake.
+            Err(err) => {
+                handshake_failures += 1;
+
+                if handshake_failures >= MAX_HANDSHAKE_FAILURES {
+                    tracing::error!("Giving up after {} failed handshakes", handshake_failures);
+                    return Err(err);
+                }
+
+                tracing::error!("Handshake failed: {}", err);
+            }
+        }
+
+        // Jitter, so that clients of a rebooted server don't all come back at once.
+        time::sleep(delay.mul_f64(rand::thread_rng().gen_range(0.5..1.0))).await;
+        delay = (delay * 2).min(max_delay);
+    }
+}
+
+// Whether to log the failure as usual rather than only at debug level, at most once per REPORT_INTERVAL.
+fn should_report(last_report: &mut Option<Instant>) -> bool {
+    if matches!(last_report, Some(last_report) if last_report.elapsed() < REPORT_INTERVAL) {
+        return false;
+    }
+
+    *last_report = Some(Instant::now());
+    true
+}
+
+// Returns a TLS connection to the server, errors are Error::Connect unless a server was reached.
+async fn connect(
+    server: Option<&Server>,
+    connector: &TlsConnector,
+) -> Result<TlsStream<TcpStream>, Error> {
+    match server {
+        Some(server) => {
+            let stream = dial(&server.hostname, server.port).await?;
+            tls(stream, &server.hostname, connector)
+                .await
+                .map_err(Error::Network)
+        }
+        None => discover(connector).await,
+    }
+}
+
+// Takes the first server found whose certificate is valid for the name it is advertised under.
+#[cfg(feature = "discovery")]
+async fn discover(connector: &TlsConnector) -> Result<TlsStream<TcpStream>, Error> {
+    let services = discovery::browse().await.map_err(Error::Connect)?;
+    if services.is_empty() {
+        return Err(Error::Connect(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No server found on the local network",
+        )));
+    }
+
+    for service in &services {
+        if !service.is_compatible() {
+            tracing::warn!(name = %service.name, address = %service.address, "Skipping server with incompatible version");
+            continue;
+        }
+
+        let hostname = match ServerName::try_from(service.host.as_str()) {
+            Ok(hostname) => hostname,
+            Err(_) => continue,
+        };
+
+        let stream = match TcpStream::connect(service.address).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                tracing::warn!(name = %service.name, address = %service.address, "Failed to connect: {}", err);
+                continue;
+            }
+        };
+
+        stream.set_nodelay(true).map_err(Error::Connect)?;
+
+        match tls(stream, &hostname, connector).await {
+            Ok(stream) => {
+                tracing::info!(name = %service.name, address = %service.address, "Connected to server");
+                return Ok(stream);
+            }
+            Err(err) => {
+                tracing::warn!(name = %service.name, address = %service.address, "TLS error: {}", err);
+            }
+        }
+    }
+
+    let candidates = services
+        .iter()
+        .map(|service| format!("{} ({})", service.host, service.address))
+        .collect::<Vec<_>>();
+
+    Err(Error::Connect(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!(
+            "No usable server among those found: {}",
+            candidates.join(", ")
+        ),
+    )))
+}
+
+#[cfg(not(feature = "discovery"))]
+async fn discover(_: &TlsConnector) -> Result<TlsStream<TcpStream>, Error> {
+    Err(Error::Connect(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Built without discovery support",
+    )))
+}
+
+// Intentionally don't impose any timeout for TCP connect.
+async fn dial(hostname: &ServerName, port: u16) -> Result<TcpStream, Error> {
     let stream = match hostname {
         ServerName::DnsName(name) => TcpStream::connect(&(name.as_ref(), port)).await,
         ServerName::IpAddress(address) => TcpStream::connect(&(*address, port)).await,
         _ => unimplemented!("Unhandled rustls ServerName variant: {:?}", hostname),
     }
-    .map_err(Error::Network)?;
+    .map_err(Error::Connect)?;
+
+    // Input is all small writes, none of which should wait for more to come.
+    stream.set_nodelay(true).map_err(Error::Connect)?;
 
     tracing::info!("Connected to server");
 
+    Ok(stream)
+}
+
+async fn tls(
+    stream: TcpStream,
+    hostname: &ServerName,
+    connector: &TlsConnector,
+) -> Result<TlsStream<TcpStream>, io::Error> {
     let stream = rkvm_net::timeout(
         rkvm_net::TLS_TIMEOUT,
         connector.connect(hostname.clone(), stream),
     )
-    .await
-    .map_err(Error::Network)?;
+    .await?;
 
     tracing::info!("TLS connected");
 
+    Ok(stream)
+}
+
+async fn handshake(
+    stream: TlsStream<TcpStream>,
+    password: &str,
+) -> Result<BufStream<TlsStream<TcpStream>>, Error> {
     let mut stream = BufStream::with_capacity(1024, 1024, stream);
 
     rkvm_net::timeout(rkvm_net::WRITE_TIMEOUT, async {
@@ -68,8 +287,8 @@ pub async fn run(
 
     if version != Version::CURRENT {
         return Err(Error::Version {
-            server: Version::CURRENT,
-            client: version,
+            server: version,
+            client: Version::CURRENT,
         });
     }
 
@@ -99,31 +318,68 @@ pub async fn run(
 
     tracing::info!("Authenticated successfully");
 
+    Ok(stream)
+}
+
+async fn serve(stream: BufStream<TlsStream<TcpStream>>) -> Result<(), Error> {
     let mut start = Instant::now();
 
-    let mut interval = time::interval(rkvm_net::PING_INTERVAL + rkvm_net::READ_TIMEOUT);
-    let mut writers = HashMap::new();
+    // The server pings right after authentication, every ping then says how long to wait for the next one.
+    let ping_timeout = time::sleep(rkvm_net::READ_TIMEOUT);
+    tokio::pin!(ping_timeout);
+
+    let mut writers = HashMap::<usize, Writer>::new();
 
-    // Interval ticks immediately after creation.
-    interval.tick().await;
+    let (reader, mut stream) = tokio::io::split(stream);
+
+    // Decoding isn't cancel safe, so the same future is polled until it completes
+    // instead of being dropped whenever an LED changes or an effect is played.
+    let mut read = Box::pin(read_update(reader));
 
     loop {
-        let update = tokio::select! {
-            update = Update::decode(&mut stream) => update.map_err(Error::Network)?,
-            _ = interval.tick() => return Err(Error::Network(io::Error::new(io::ErrorKind::TimedOut, "Ping timed out"))),
+        let request = future::poll_fn(|cx| {
+            for (id, writer) in writers.iter_mut() {
+                if let Poll::Ready(result) = writer.poll_read_request(cx) {
+                    return Poll::Ready((*id, result));
+                }
+            }
+
+            Poll::Pending
+        });
+
+        let (reader, update) = tokio::select! {
+            result = &mut read => result,
+            (id, request) = request => {
+                let feedback = match request.map_err(Error::Input)? {
+                    DeviceRequest::Led(event) => {
+                        tracing::debug!(id = %id, led = ?event.led, state = %event.state, "Sending LED change");
+                        Feedback::Led { id, event }
+                    }
+                    DeviceRequest::Ff(request) => {
+                        tracing::trace!(id = %id, request = ?request, "Sending force feedback request");
+                        Feedback::Ff { id, request }
+                    }
+                };
+
+                rkvm_net::timeout(rkvm_net::WRITE_TIMEOUT, async {
+                    feedback.encode(&mut stream).await?;
+                    stream.flush().await?;
+
+                    Ok(())
+                })
+                .await
+                .map_err(Error::Network)?;
+
+                continue;
+            }
+            _ = &mut ping_timeout => return Err(Error::Network(io::Error::new(io::ErrorKind::TimedOut, "Ping timed out"))),
         };
 
+        read = Box::pin(read_update(reader));
+        let update = update.map_err(Error::Network)?;
+
         match update {
-            Update::CreateDevice {
-                id,
-                name,
-                vendor,
-                product,
-                version,
-                rel,
-                abs,
-                keys,
-            } => {
+            Update::CreateDevice { id, capabilities } => {
                 let entry = writers.entry(id);
                 if let Entry::Occupied(_) = entry {
                     return Err(Error::Network(io::Error::new(
@@ -132,29 +388,20 @@ pub async fn run(
                     )));
                 }
 
-                let writer = async {
-                    Writer::builder()?
-                        .name(&name)
-                        .vendor(vendor)
-                        .product(product)
-                        .version(version)
-                        .rel(rel)?
-                        .abs(abs)?
-                        .key(keys)?
-                        .build()
-                        .await
-                }
-                .await
-                .map_err(Error::Input)?;
+                let writer = WriterBuilder::from_capabilities(&capabilities)
+                    .map_err(Error::Input)?
+                    .build()
+                    .await
+                    .map_err(Error::Input)?;
 
                 entry.or_insert(writer);
 
                 tracing::info!(
                     id = %id,
-                    name = ?name,
-                    vendor = %vendor,
-                    product = %product,
-                    version = %version,
+                    name = ?capabilities.name,
+                    vendor = %capabilities.vendor,
+                    product = %capabilities.product,
+                    version = %capabilities.version,
                     "Created new device"
                 );
             }
@@ -180,15 +427,32 @@ pub async fn run(
 
                 tracing::trace!(id = %id, "Wrote an event to device");
             }
-            Update::Ping => {
+            Update::Frame { id, events } => {
+                let writer = writers.get_mut(&id).ok_or_else(|| {
+                    Error::Network(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Server sent a frame to a nonexistent device",
+                    ))
+                })?;
+
+                // Limited to the maximum number of events per frame of the writer, the server could send any number.
+                let count = events.len();
+                writer
+                    .write_frame(&events.into())
+                    .await
+                    .map_err(Error::Input)?;
+
+                tracing::trace!(id = %id, events = %count, "Wrote a frame to device");
+            }
+            Update::Ping { timeout } => {
                 let duration = start.elapsed();
                 tracing::debug!(duration = ?duration, "Received ping");
 
                 start = Instant::now();
-                interval.reset();
+                ping_timeout.as_mut().reset(time::Instant::now() + timeout);
 
                 rkvm_net::timeout(rkvm_net::WRITE_TIMEOUT, async {
-                    Pong.encode(&mut stream).await?;
+                    Feedback::Pong.encode(&mut stream).await?;
                     stream.flush().await?;
 
                     Ok(())
@@ -202,3 +466,32 @@ pub async fn run(
         }
     }
 }
+
+async fn read_update<R: AsyncRead + Send + Unpin>(mut reader: R) -> (R, Result<Update, io::Error>) {
+    let update = Update::decode(&mut reader).await;
+    (reader, update)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn version_mismatch() {
+        let server = Version::decode(&mut b"rkvm\x10\x00".as_slice())
+            .await
+            .unwrap();
+        let err = Error::Version {
+            server,
+            client: Version::CURRENT,
+        };
+
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Incompatible server version, server speaks v16, client speaks {}",
+                Version::CURRENT
+            )
+        );
+    }
+}
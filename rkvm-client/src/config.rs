@@ -9,9 +9,29 @@ use tokio_rustls::rustls::ServerName;
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
-    pub server: Server,
+    // None if omitted or "auto", servers are then looked for on the local network.
+    #[serde(default, deserialize_with = "server")]
+    pub server: Option<Server>,
     pub certificate: PathBuf,
     pub password: String,
+    // In seconds, the delay between reconnection attempts doubles up to this.
+    #[serde(default = "default_max_reconnect_delay")]
+    pub max_reconnect_delay: u64,
+}
+
+fn default_max_reconnect_delay() -> u64 {
+    30
+}
+
+fn server<'de, D>(deserializer: D) -> Result<Option<Server>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let data = String::deserialize(deserializer)?;
+    match data.as_str() {
+        "auto" => Ok(None),
+        data => ServerVisitor.visit_str(data).map(Some),
+    }
 }
 
 pub struct Server {
@@ -6,6 +6,7 @@ use clap::Parser;
 use config::Config;
 use std::path::PathBuf;
 use std::process::ExitCode;
+use std::time::Duration;
 use tokio::{fs, signal};
 use tracing::subscriber;
 use tracing_subscriber::filter::{EnvFilter, LevelFilter};
@@ -17,6 +18,11 @@ use tracing_subscriber::prelude::*;
 struct Args {
     #[clap(help = "Path to configuration file")]
     config_path: PathBuf,
+    #[structopt(
+        help = "Exit when the connection to the server is lost instead of reconnecting",
+        long
+    )]
+    no_reconnect: bool,
 }
 
 #[tokio::main]
@@ -48,6 +54,11 @@ async fn main() -> ExitCode {
         }
     };
 
+    if config.server.is_none() && !cfg!(feature = "discovery") {
+        tracing::error!("No server set, and rkvm-client was built without the discovery feature to look for one");
+        return ExitCode::FAILURE;
+    }
+
     let connector = match tls::configure(&config.certificate).await {
         Ok(connector) => connector,
         Err(err) => {
@@ -56,8 +67,13 @@ async fn main() -> ExitCode {
         }
     };
 
+    let max_delay = match args.no_reconnect {
+        true => None,
+        false => Some(Duration::from_secs(config.max_reconnect_delay)),
+    };
+
     tokio::select! {
-        result = client::run(&config.server.hostname, config.server.port, connector, &config.password) => {
+        result = client::run(config.server.as_ref(), connector, &config.password, max_delay) => {
             if let Err(err) = result {
                 tracing::error!("Error: {}", err);
                 return ExitCode::FAILURE;
@@ -0,0 +1,62 @@
+use clap::Parser;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[clap(name = "rkvm-ctl", about = "A tool to control a running rkvm server")]
+struct Args {
+    #[clap(
+        long,
+        short,
+        help = "Path to the control socket of the server",
+        default_value = "/run/rkvm-server.sock"
+    )]
+    socket: PathBuf,
+    #[clap(
+        required = true,
+        help = "The request to make, one of list, status, switch <machine> or switch-next"
+    )]
+    request: Vec<String>,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    match run(&args) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// Prints the reply, returning whether the request succeeded.
+fn run(args: &Args) -> Result<bool, io::Error> {
+    let mut stream = UnixStream::connect(&args.socket)?;
+    writeln!(stream, "{}", args.request.join(" "))?;
+
+    let mut lines = BufReader::new(stream).lines();
+    let status = lines.next().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::UnexpectedEof, "Server closed the connection")
+    })??;
+
+    if let Some(err) = status.strip_prefix("error ") {
+        eprintln!("Error: {}", err);
+        return Ok(false);
+    }
+
+    for line in lines {
+        let line = line?;
+        if line.is_empty() {
+            break;
+        }
+
+        println!("{}", line);
+    }
+
+    Ok(true)
+}
@@ -1,2 +1,3 @@
 #include <libevdev/libevdev.h>
-#include <libevdev/libevdev-uinput.h>
\ No newline at end of file
+#include <libevdev/libevdev-uinput.h>
+#include <linux/uinput.h>
@@ -3,7 +3,7 @@ use crate::glue;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum AbsEvent {
     Axis { axis: AbsAxis, value: i32 },
     MtToolType { value: ToolType },
@@ -160,7 +160,7 @@ impl Convert for AbsAxis {
 }
 
 // See struct input_absinfo.
-#[derive(Clone, Copy, Deserialize, Serialize, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Debug)]
 pub struct AbsInfo {
     pub min: i32,
     pub max: i32,
@@ -169,7 +169,7 @@ pub struct AbsInfo {
     pub resolution: i32,
 }
 
-#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
 pub enum ToolType {
     Finger,
     Pen,
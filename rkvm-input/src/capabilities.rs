@@ -0,0 +1,34 @@
+use crate::abs::{AbsAxis, AbsInfo};
+use crate::ff::FfEffectType;
+use crate::key::Key;
+use crate::led::Led;
+use crate::msc::Msc;
+use crate::prop::InputProp;
+use crate::rel::RelAxis;
+use crate::switch::Switch;
+use crate::sync::SyncEvent;
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+
+// Everything needed to recreate a device elsewhere, see `Interceptor::capabilities` and
+// `WriterBuilder::from_capabilities`. Codes the enums don't model are left out.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeviceCapabilities {
+    pub name: CString,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
+    pub rel: HashSet<RelAxis>,
+    // Including the resolution, which absolute pointing devices need to map to the right area.
+    pub abs: HashMap<AbsAxis, AbsInfo>,
+    pub keys: HashSet<Key>,
+    pub syn: HashSet<SyncEvent>,
+    pub msc: HashSet<Msc>,
+    pub leds: HashSet<Led>,
+    pub ff: HashSet<FfEffectType>,
+    pub switches: HashSet<Switch>,
+    pub props: HashSet<InputProp>,
+    pub repeat: Option<(i32, i32)>,
+}
@@ -5,3 +5,98 @@ pub trait Convert: Sized {
 
     fn to_raw(&self) -> Option<Self::Raw>;
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::abs::AbsAxis;
+    use crate::glue;
+    use crate::key::{Button, Key, Keyboard};
+    use crate::led::Led;
+    use crate::msc::Msc;
+    use crate::prop::InputProp;
+    use crate::rel::RelAxis;
+    use crate::rep::Rep;
+    use crate::switch::Switch;
+    use crate::sync::SyncEvent;
+
+    use serde::de::value::{Error, U32Deserializer};
+    use serde::de::DeserializeOwned;
+    use std::fmt::Debug;
+
+    // Every variant of a fieldless enum, in declaration order. Goes through the derived
+    // Deserialize impl, which accepts variant indices.
+    fn variants<T: DeserializeOwned>() -> Vec<T> {
+        (0..)
+            .map_while(|index| T::deserialize(U32Deserializer::<Error>::new(index)).ok())
+            .collect()
+    }
+
+    // Aliases share their code with another variant, which is what that code decodes to.
+    fn check<T>(count: u32, aliases: &[T])
+    where
+        T: Convert<Raw = u16> + DeserializeOwned + PartialEq + Debug,
+    {
+        let variants = variants::<T>();
+        assert!(!variants.is_empty());
+
+        for variant in variants {
+            // Some variants only exist with newer kernel headers.
+            let raw = match variant.to_raw() {
+                Some(raw) => raw,
+                None => continue,
+            };
+
+            if aliases.contains(&variant) {
+                assert_ne!(T::from_raw(raw), Some(variant));
+            } else {
+                assert_eq!(T::from_raw(raw), Some(variant));
+            }
+        }
+
+        for raw in 0..count as u16 {
+            if let Some(value) = T::from_raw(raw) {
+                assert_eq!(value.to_raw(), Some(raw), "{:?}", value);
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        // KEY_BRIGHTNESS_TOGGLE is defined as KEY_DISPLAYTOGGLE.
+        check(glue::KEY_CNT, &[Keyboard::DisplayToggle]);
+        check::<Button>(glue::KEY_CNT, &[]);
+        check::<RelAxis>(glue::REL_CNT, &[]);
+        check::<AbsAxis>(glue::ABS_CNT, &[]);
+        check::<SyncEvent>(glue::SYN_CNT, &[]);
+        check::<Msc>(glue::MSC_CNT, &[]);
+        check::<Led>(glue::LED_CNT, &[]);
+        check::<Rep>(glue::REP_CNT, &[]);
+        check::<InputProp>(glue::INPUT_PROP_CNT, &[]);
+        check::<Switch>(glue::SW_CNT, &[]);
+
+        let keys = variants::<Keyboard>()
+            .into_iter()
+            .filter(|key| *key != Keyboard::DisplayToggle)
+            .map(Key::Key);
+
+        for key in keys {
+            assert_eq!(Key::from_raw(key.to_raw().unwrap()), Some(key));
+        }
+
+        for key in variants::<Button>().into_iter().map(Key::Button) {
+            assert_eq!(Key::from_raw(key.to_raw().unwrap()), Some(key));
+        }
+
+        for raw in 0..glue::KEY_CNT as u16 {
+            if Keyboard::from_raw(raw).is_none() && Button::from_raw(raw).is_none() {
+                assert_eq!(Key::from_raw(raw), Some(Key::Other(raw)));
+                assert_eq!(Key::Other(raw).to_raw(), Some(raw));
+            }
+        }
+
+        assert_eq!(Key::from_raw(glue::KEY_CNT as u16), None);
+        assert_eq!(Key::Other(glue::KEY_CNT as u16).to_raw(), None);
+        assert_eq!(Switch::from_raw(glue::SW_CNT as u16), None);
+    }
+}
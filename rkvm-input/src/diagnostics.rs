@@ -0,0 +1,357 @@
+use crate::event::Event;
+use crate::frame::Frame;
+use crate::glue;
+use crate::key::{Key, KeyEvent, Keyboard};
+use crate::rel::{RelAxis, RelEvent};
+use crate::sync::SyncEvent;
+use crate::writer::{Writer, WriterBuilder};
+
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use std::ffi::CStr;
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time;
+
+// The pointer traces a square of SQUARE_STEPS * SQUARE_STEP units per side, clockwise from the starting position.
+const SQUARE_STEP: i32 = 10;
+const SQUARE_STEPS: usize = 10;
+
+// Typed after the square is complete. The header and footer keys make it easy to find the pattern in a capture
+// that also contains unrelated events.
+const TEXT: &[Keyboard] = &[
+    Keyboard::LeftBrace,
+    Keyboard::R,
+    Keyboard::K,
+    Keyboard::V,
+    Keyboard::M,
+    Keyboard::RightBrace,
+];
+
+// Keeps the pattern slow enough to be followed visually.
+const FRAME_DELAY: Duration = Duration::from_millis(10);
+
+// Returns the frames making up the test pattern, each one terminated by a SyncEvent::All.
+pub fn test_pattern() -> Vec<Vec<Event>> {
+    let mut frames = Vec::new();
+
+    for (x, y) in [(1, 0), (0, 1), (-1, 0), (0, -1)] {
+        for _ in 0..SQUARE_STEPS {
+            let mut frame = Vec::new();
+
+            for (axis, value) in [(RelAxis::X, x), (RelAxis::Y, y)] {
+                if value != 0 {
+                    frame.push(Event::Rel(RelEvent {
+                        axis,
+                        value: value * SQUARE_STEP,
+                    }));
+                }
+            }
+
+            frame.push(Event::Sync(SyncEvent::All));
+            frames.push(frame);
+        }
+    }
+
+    for key in TEXT {
+        for down in [true, false] {
+            frames.push(vec![
+                Event::Key(KeyEvent {
+                    key: Key::Key(*key),
+                    down,
+                }),
+                Event::Sync(SyncEvent::All),
+            ]);
+        }
+    }
+
+    frames
+}
+
+// Checks whether the captured events contain the complete test pattern, in order and without gaps.
+pub fn verify_test_pattern(captured: &[Event]) -> bool {
+    let pattern = test_pattern().into_iter().flatten().collect::<Vec<_>>();
+
+    captured
+        .windows(pattern.len())
+        .any(|window| window == pattern.as_slice())
+}
+
+// Returns a builder with everything the test pattern needs enabled.
+pub fn test_pattern_builder() -> Result<WriterBuilder, Error> {
+    let name = CStr::from_bytes_with_nul(b"rkvm test pattern\0").unwrap();
+
+    Writer::builder()?
+        .name(name)
+        .rel([RelAxis::X, RelAxis::Y])?
+        .key(TEXT.iter().copied().map(Key::Key))
+}
+
+pub async fn run_test_pattern(writer: &mut Writer) -> Result<(), Error> {
+    for frame in test_pattern() {
+        for event in &frame {
+            writer.write(event).await?;
+        }
+
+        time::sleep(FRAME_DELAY).await;
+    }
+
+    Ok(())
+}
+
+// Measures the report rate of a device by counting the frames passing through a stream of frames,
+// such as `Interceptor::frames`, without holding any of them back.
+// The rate is based on the kernel timestamps, so it isn't skewed by how fast the stream is polled.
+pub struct ReportRate<S> {
+    stream: Pin<Box<S>>,
+    sample: Duration,
+    frames: u64,
+    elapsed: Duration,
+}
+
+impl<S: Stream<Item = Result<(Frame, Duration), Error>>> ReportRate<S> {
+    pub fn new(stream: S, sample: Duration) -> Self {
+        Self {
+            stream: Box::pin(stream),
+            sample,
+            frames: 0,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    // Frames per second, available once frames spanning at least the sample duration went through.
+    pub fn rate(&self) -> Option<f64> {
+        if self.elapsed.is_zero() || self.elapsed < self.sample {
+            return None;
+        }
+
+        Some(self.frames as f64 / self.elapsed.as_secs_f64())
+    }
+
+    pub fn reset(&mut self) {
+        self.frames = 0;
+        self.elapsed = Duration::ZERO;
+    }
+}
+
+impl<S: Stream<Item = Result<(Frame, Duration), Error>>> Stream for ReportRate<S> {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = self.stream.as_mut().poll_next(cx);
+
+        // The first frame has no predecessor to measure the time from.
+        if let Poll::Ready(Some(Ok((_, elapsed)))) = &item {
+            if !elapsed.is_zero() || self.frames > 0 {
+                self.frames += 1;
+                self.elapsed += *elapsed;
+            }
+        }
+
+        item
+    }
+}
+
+// Devices created through uinput that don't impersonate the bus of a real device are recognized by this prefix.
+pub const VIRTUAL_NAME_PREFIX: &str = "rkvm";
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DeviceInfo {
+    pub name: String,
+    // The event device node, if udev created one.
+    pub path: Option<PathBuf>,
+    pub syspath: PathBuf,
+    pub bus_type: u16,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
+}
+
+impl DeviceInfo {
+    pub fn match_key(&self, spec: &MatchSpec) -> bool {
+        spec.matches(self)
+    }
+}
+
+// Selects devices in configuration files, all given criteria have to match. An empty spec matches every device.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MatchSpec {
+    // A glob, where * matches any number of characters and ? a single one.
+    pub name: Option<String>,
+    pub bus_type: Option<u16>,
+    pub vendor: Option<u16>,
+    pub product: Option<u16>,
+}
+
+impl MatchSpec {
+    // Either a vendor and product ID pair in hex, such as "046d:c52b", or a name glob.
+    pub fn parse(pattern: &str) -> Self {
+        let id = |id: &str| match id.len() {
+            4 => u16::from_str_radix(id, 16).ok(),
+            _ => None,
+        };
+
+        let ids = pattern
+            .split_once(':')
+            .and_then(|(vendor, product)| Some((id(vendor)?, id(product)?)));
+
+        match ids {
+            Some((vendor, product)) => Self {
+                vendor: Some(vendor),
+                product: Some(product),
+                ..Default::default()
+            },
+            None => Self {
+                name: Some(pattern.to_owned()),
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn matches(&self, device: &DeviceInfo) -> bool {
+        let name = match &self.name {
+            Some(name) => glob(name, &device.name),
+            None => true,
+        };
+
+        name && matches_id(self.bus_type, device.bus_type)
+            && matches_id(self.vendor, device.vendor)
+            && matches_id(self.product, device.product)
+    }
+}
+
+fn matches_id(expected: Option<u16>, id: u16) -> bool {
+    match expected {
+        Some(expected) => expected == id,
+        None => true,
+    }
+}
+
+fn glob(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the last star if the rest fails to match, letting it consume one more character.
+    let mut star = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// Lists the virtual devices which look like they were created by rkvm, for example to find the ones
+// left behind by an instance that crashed. Writers are created on the virtual bus unless told otherwise,
+// devices of other uinput users on the same bus are only told apart if they carry the name prefix.
+pub fn list_virtual_devices() -> Vec<DeviceInfo> {
+    let entries = match fs::read_dir("/sys/devices/virtual/input") {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut devices = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| read_device(&entry.path()))
+        .filter(|device| {
+            device.bus_type == glue::BUS_VIRTUAL as u16
+                || device.name.starts_with(VIRTUAL_NAME_PREFIX)
+        })
+        .collect::<Vec<_>>();
+
+    devices.sort_by(|a, b| a.syspath.cmp(&b.syspath));
+    devices
+}
+
+fn read_device(syspath: &Path) -> Option<DeviceInfo> {
+    let read = |name: &str| fs::read_to_string(syspath.join(name)).ok();
+    let read_id = |name: &str| u16::from_str_radix(read(name)?.trim(), 16).ok();
+
+    let name = read("name")?.trim_end().to_owned();
+
+    let path = fs::read_dir(syspath)
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.file_name())
+        .find(|name| name.to_string_lossy().starts_with("event"))
+        .map(|name| Path::new("/dev/input").join(name));
+
+    Some(DeviceInfo {
+        name,
+        path,
+        syspath: syspath.to_owned(),
+        bus_type: read_id("id/bustype")?,
+        vendor: read_id("id/vendor")?,
+        product: read_id("id/product")?,
+        version: read_id("id/version")?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pattern_verifies() {
+        let mut captured = vec![Event::Sync(SyncEvent::All)];
+        captured.extend(test_pattern().into_iter().flatten());
+
+        assert!(verify_test_pattern(&captured));
+
+        captured.remove(captured.len() - 2);
+        assert!(!verify_test_pattern(&captured));
+    }
+
+    #[test]
+    fn spec_matches() {
+        let device = DeviceInfo {
+            name: "Microsoft X-Box 360 pad".to_owned(),
+            path: None,
+            syspath: PathBuf::new(),
+            bus_type: 0x03,
+            vendor: 0x045e,
+            product: 0x028e,
+            version: 0x0114,
+        };
+
+        let spec = |name: &str, vendor| MatchSpec {
+            name: Some(name.to_owned()),
+            vendor,
+            ..Default::default()
+        };
+
+        assert!(device.match_key(&MatchSpec::default()));
+        assert!(device.match_key(&spec("*X-Box*", Some(0x045e))));
+        assert!(device.match_key(&spec("Microsoft ?-Box 360 *", None)));
+        assert!(!device.match_key(&spec("*X-Box*", Some(0x046d))));
+        assert!(!device.match_key(&spec("*keyboard*", None)));
+
+        assert!(device.match_key(&MatchSpec::parse("045e:028e")));
+        assert!(device.match_key(&MatchSpec::parse("*X-Box*")));
+        assert!(!device.match_key(&MatchSpec::parse("046d:c52b")));
+        assert_eq!(MatchSpec::parse("a:b").name.as_deref(), Some("a:b"));
+    }
+}
@@ -24,9 +24,17 @@ impl Evdev {
     }
 
     pub async fn open(path: &Path) -> Result<Self, Error> {
+        Self::open_with(path, true).await
+    }
+
+    pub async fn open_read_only(path: &Path) -> Result<Self, Error> {
+        Self::open_with(path, false).await
+    }
+
+    async fn open_with(path: &Path, write: bool) -> Result<Self, Error> {
         let file = OpenOptions::new()
             .read(true)
-            .write(true)
+            .write(write)
             .custom_flags(libc::O_NONBLOCK)
             .open(path)
             .await?
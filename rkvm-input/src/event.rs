@@ -1,14 +1,98 @@
 use crate::abs::AbsEvent;
+use crate::convert::Convert;
+use crate::glue;
 use crate::key::KeyEvent;
+use crate::led::LedEvent;
+use crate::msc::MscEvent;
 use crate::rel::RelEvent;
+use crate::rep::RepEvent;
+use crate::switch::SwitchEvent;
 use crate::sync::SyncEvent;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Event {
     Rel(RelEvent),
     Abs(AbsEvent),
     Key(KeyEvent),
+    Msc(MscEvent),
+    Rep(RepEvent),
     Sync(SyncEvent),
+    Switch(SwitchEvent),
+    // Variants are encoded by their position, new ones go last.
+    Led(LedEvent),
+}
+
+impl Event {
+    pub(crate) fn to_raw(&self) -> Option<(u16, u16, i32)> {
+        let (r#type, code, value) = match self {
+            Self::Rel(RelEvent { axis, value }) => (EventType::Rel, axis.to_raw(), Some(*value)),
+            Self::Abs(event) => match event {
+                AbsEvent::Axis { axis, value } => (EventType::Abs, axis.to_raw(), Some(*value)),
+                AbsEvent::MtToolType { value } => (
+                    EventType::Abs,
+                    Some(glue::ABS_MT_TOOL_TYPE as _),
+                    value.to_raw(),
+                ),
+            },
+            Self::Key(KeyEvent { down, key }) => (EventType::Key, key.to_raw(), Some(*down as _)),
+            Self::Led(LedEvent { led, state }) => (EventType::Led, led.to_raw(), Some(*state as _)),
+            Self::Msc(MscEvent { code, value }) => (EventType::Msc, code.to_raw(), Some(*value)),
+            Self::Rep(RepEvent { code, value }) => (EventType::Rep, code.to_raw(), Some(*value)),
+            Self::Sync(event) => (EventType::Sync, event.to_raw(), Some(0)),
+            Self::Switch(SwitchEvent { switch, value }) => {
+                (EventType::Switch, switch.to_raw(), Some(*value as _))
+            }
+        };
+
+        Some((r#type.to_raw()?, code?, value?))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum EventType {
+    Rel,
+    Abs,
+    Key,
+    Led,
+    Msc,
+    Rep,
+    Sync,
+    Switch,
+}
+
+impl Convert for EventType {
+    type Raw = u16;
+
+    fn from_raw(raw: Self::Raw) -> Option<Self> {
+        let r#type = match raw as _ {
+            glue::EV_REL => Self::Rel,
+            glue::EV_ABS => Self::Abs,
+            glue::EV_KEY => Self::Key,
+            glue::EV_LED => Self::Led,
+            glue::EV_MSC => Self::Msc,
+            glue::EV_REP => Self::Rep,
+            glue::EV_SYN => Self::Sync,
+            glue::EV_SW => Self::Switch,
+            _ => return None,
+        };
+
+        Some(r#type)
+    }
+
+    fn to_raw(&self) -> Option<Self::Raw> {
+        let raw = match self {
+            Self::Rel => glue::EV_REL,
+            Self::Abs => glue::EV_ABS,
+            Self::Key => glue::EV_KEY,
+            Self::Led => glue::EV_LED,
+            Self::Msc => glue::EV_MSC,
+            Self::Rep => glue::EV_REP,
+            Self::Sync => glue::EV_SYN,
+            Self::Switch => glue::EV_SW,
+        };
+
+        Some(raw as _)
+    }
 }
@@ -0,0 +1,127 @@
+use crate::convert::Convert;
+use crate::glue::{self, ff_effect};
+
+use serde::{Deserialize, Serialize};
+use std::mem;
+
+// Only rumble is supported for now, it is what gamepads use and all of them support it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub enum FfEffectType {
+    Rumble,
+}
+
+impl Convert for FfEffectType {
+    type Raw = u16;
+
+    fn from_raw(raw: Self::Raw) -> Option<Self> {
+        let effect = match raw as _ {
+            glue::FF_RUMBLE => Self::Rumble,
+            _ => return None,
+        };
+
+        Some(effect)
+    }
+
+    fn to_raw(&self) -> Option<Self::Raw> {
+        let raw = match self {
+            Self::Rumble => glue::FF_RUMBLE,
+        };
+
+        Some(raw as _)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub struct FfEffect {
+    pub kind: FfEffectKind,
+    // Both in milliseconds, a length of 0 keeps playing until the effect is stopped.
+    pub length: u16,
+    pub delay: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub enum FfEffectKind {
+    // The magnitudes of the heavy and the light motor.
+    Rumble { strong: u16, weak: u16 },
+}
+
+impl FfEffect {
+    pub fn effect_type(&self) -> FfEffectType {
+        match self.kind {
+            FfEffectKind::Rumble { .. } => FfEffectType::Rumble,
+        }
+    }
+
+    pub(crate) fn from_raw(raw: &ff_effect) -> Option<Self> {
+        let kind = match FfEffectType::from_raw(raw.type_)? {
+            FfEffectType::Rumble => {
+                let rumble = unsafe { raw.u.rumble };
+
+                FfEffectKind::Rumble {
+                    strong: rumble.strong_magnitude,
+                    weak: rumble.weak_magnitude,
+                }
+            }
+        };
+
+        Some(Self {
+            kind,
+            length: raw.replay.length,
+            delay: raw.replay.delay,
+        })
+    }
+
+    // An ID of -1 makes the kernel allocate a new one.
+    pub(crate) fn to_raw(self, id: i16) -> ff_effect {
+        let mut raw: ff_effect = unsafe { mem::zeroed() };
+        raw.id = id;
+        raw.replay.length = self.length;
+        raw.replay.delay = self.delay;
+
+        match self.kind {
+            FfEffectKind::Rumble { strong, weak } => {
+                raw.type_ = glue::FF_RUMBLE as _;
+                raw.u.rumble = glue::ff_rumble_effect {
+                    strong_magnitude: strong,
+                    weak_magnitude: weak,
+                };
+            }
+        }
+
+        raw
+    }
+}
+
+// Requests userspace makes to a force feedback device. The IDs are the ones of the device the request was made to,
+// see `Interceptor::apply_ff` for replaying them on another device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub enum FfRequest {
+    // Replaces the effect if the ID is already in use.
+    Upload { id: i16, effect: FfEffect },
+    Erase { id: i16 },
+    // Plays the effect the given number of times, 0 stops it.
+    Play { id: i16, count: i32 },
+    // Scales the magnitude of all effects, 0xffff being full strength.
+    Gain(u16),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn raw_round_trip() {
+        let effect = FfEffect {
+            kind: FfEffectKind::Rumble {
+                strong: 0x8000,
+                weak: 0xffff,
+            },
+            length: 500,
+            delay: 20,
+        };
+
+        let raw = effect.to_raw(3);
+        assert_eq!(raw.id, 3);
+        assert_eq!(FfEffect::from_raw(&raw), Some(effect));
+    }
+}
@@ -0,0 +1,211 @@
+use crate::abs::AbsEvent;
+use crate::convert::Convert;
+use crate::event::{Event, EventType};
+use crate::sync::SyncEvent;
+
+use serde::{Deserialize, Serialize};
+use std::slice;
+
+// High enough to never be hit by real devices, even multitouch ones with all slots in use.
+pub(crate) const MAX_FRAME_EVENTS: usize = 4096;
+
+// A group of events delimited by SyncEvent::All, which the kernel treats as a single atomic change.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Frame {
+    events: Vec<Event>,
+    // Set when the frame was synthesized to catch up with the device after the kernel dropped events.
+    resync: bool,
+}
+
+impl Frame {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn push(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.resync = false;
+    }
+
+    pub fn is_resync(&self) -> bool {
+        self.resync
+    }
+
+    pub fn set_resync(&mut self, resync: bool) {
+        self.resync = resync;
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, Event> {
+        self.events.iter()
+    }
+
+    // Yields every distinct (type, code) pair the frame contains, in order of first appearance.
+    // Sync events are framing rather than state and are left out.
+    // Frames are small, so looking back through the frame is cheaper than allocating a set.
+    pub fn touched_codes(&self) -> impl Iterator<Item = (EventType, u16)> + '_ {
+        self.events
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, event)| {
+                let code = touched_code(event)?;
+                let seen = self.events[..i]
+                    .iter()
+                    .any(|event| touched_code(event) == Some(code));
+
+                (!seen).then_some(code)
+            })
+    }
+}
+
+impl From<Vec<Event>> for Frame {
+    fn from(events: Vec<Event>) -> Self {
+        Self {
+            events,
+            resync: false,
+        }
+    }
+}
+
+impl From<Frame> for Vec<Event> {
+    fn from(frame: Frame) -> Self {
+        frame.events
+    }
+}
+
+impl IntoIterator for Frame {
+    type Item = Event;
+    type IntoIter = std::vec::IntoIter<Event>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.events.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Frame {
+    type Item = &'a Event;
+    type IntoIter = slice::Iter<'a, Event>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.events.iter()
+    }
+}
+
+// A frame for protocol A multitouch devices, which report every contact in full and anonymously,
+// each one terminated by SyncEvent::Mt. Converting it into a frame takes care of the framing.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MtFrame {
+    contacts: Vec<Vec<AbsEvent>>,
+    events: Vec<Event>,
+}
+
+impl MtFrame {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_contact(&mut self, contact: Vec<AbsEvent>) {
+        self.contacts.push(contact);
+    }
+
+    // Events not tied to a contact, such as BTN_TOUCH or the single touch axes, written after the contacts.
+    pub fn push(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    pub fn contacts(&self) -> &[Vec<AbsEvent>] {
+        &self.contacts
+    }
+}
+
+impl From<MtFrame> for Frame {
+    fn from(frame: MtFrame) -> Self {
+        let mut events = Vec::new();
+        for contact in frame.contacts.iter().filter(|contact| !contact.is_empty()) {
+            events.extend(contact.iter().copied().map(Event::Abs));
+            events.push(Event::Sync(SyncEvent::Mt));
+        }
+
+        // The lift of the last contact is reported as a frame without any.
+        if events.is_empty() {
+            events.push(Event::Sync(SyncEvent::Mt));
+        }
+
+        events.extend(frame.events);
+        events.push(Event::Sync(SyncEvent::All));
+
+        events.into()
+    }
+}
+
+fn touched_code(event: &Event) -> Option<(EventType, u16)> {
+    let (r#type, code, _) = event.to_raw()?;
+    let r#type = EventType::from_raw(r#type)?;
+
+    (r#type != EventType::Sync).then_some((r#type, code))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::abs::AbsAxis;
+    use crate::rel::{RelAxis, RelEvent};
+
+    #[test]
+    fn touched_codes_are_distinct() {
+        let rel = |axis, value| Event::Rel(RelEvent { axis, value });
+        let frame = Frame::from(vec![
+            rel(RelAxis::X, 1),
+            rel(RelAxis::Y, 2),
+            rel(RelAxis::X, 3),
+            Event::Sync(SyncEvent::All),
+        ]);
+
+        let codes = frame.touched_codes().collect::<Vec<_>>();
+        let expected = [RelAxis::X, RelAxis::Y]
+            .map(|axis| (EventType::Rel, axis.to_raw().unwrap()))
+            .to_vec();
+
+        assert_eq!(codes, expected);
+    }
+
+    #[test]
+    fn mt_frame_is_framed() {
+        let abs = |axis, value| AbsEvent::Axis { axis, value };
+
+        let mut frame = MtFrame::new();
+        frame.push_contact(vec![abs(AbsAxis::MtPositionX, 1)]);
+        frame.push_contact(vec![abs(AbsAxis::MtPositionX, 2)]);
+
+        assert_eq!(
+            Frame::from(frame).events(),
+            [
+                Event::Abs(abs(AbsAxis::MtPositionX, 1)),
+                Event::Sync(SyncEvent::Mt),
+                Event::Abs(abs(AbsAxis::MtPositionX, 2)),
+                Event::Sync(SyncEvent::Mt),
+                Event::Sync(SyncEvent::All),
+            ]
+        );
+
+        assert_eq!(
+            Frame::from(MtFrame::new()).events(),
+            [Event::Sync(SyncEvent::Mt), Event::Sync(SyncEvent::All)]
+        );
+    }
+}
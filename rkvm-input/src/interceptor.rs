@@ -1,50 +1,130 @@
+mod battery;
 mod caps;
+mod set;
 
+pub use battery::{BatteryInfo, BatteryStatus};
 pub use caps::{AbsCaps, KeyCaps, RelCaps};
+pub use set::InterceptorSet;
 
 use crate::abs::{AbsAxis, AbsEvent, ToolType};
+use crate::capabilities::DeviceCapabilities;
 use crate::convert::Convert;
+use crate::diagnostics::{DeviceInfo, ReportRate};
 use crate::evdev::Evdev;
 use crate::event::Event;
-use crate::glue;
+use crate::ff::{FfEffectType, FfRequest};
+use crate::frame::{Frame, MAX_FRAME_EVENTS};
+use crate::glue::{self, ff_effect, input_event};
 use crate::key::{Key, KeyEvent};
+use crate::led::{Led, LedEvent};
+use crate::msc::{Msc, MscEvent};
+use crate::prop::InputProp;
 use crate::registry::{Entry, Handle, Registry};
 use crate::rel::{RelAxis, RelEvent};
+use crate::set::KeySet;
+use crate::state::DeviceState;
+use crate::switch::{Switch, SwitchEvent};
 use crate::sync::SyncEvent;
 use crate::writer::Writer;
 
-use std::collections::VecDeque;
+use futures::stream::{self, Stream};
+use std::collections::{HashMap, VecDeque};
 use std::ffi::CStr;
 use std::fs;
 use std::io::{Error, ErrorKind};
-use std::mem::MaybeUninit;
-use std::path::Path;
+use std::mem::{self, MaybeUninit};
+use std::os::fd::AsRawFd;
+use std::os::raw::{c_int, c_ulong};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use thiserror::Error;
+use tokio::sync::watch::Receiver;
+
+// _IOW('E', 0x80, struct ff_effect) and _IOW('E', 0x81, int).
+const EVIOCSFF: c_ulong =
+    (1 << 30) | ((mem::size_of::<ff_effect>() as c_ulong) << 16) | ((b'E' as c_ulong) << 8) | 0x80;
+const EVIOCRMFF: c_ulong = (1 << 30) | (4 << 16) | ((b'E' as c_ulong) << 8) | 0x81;
 
 pub struct Interceptor {
+    path: PathBuf,
+    // Read when opening, before the bus type is changed to keep the writer apart from real devices.
+    info: DeviceInfo,
     evdev: Evdev,
-    writer: Writer,
+    // None for observers and passive interceptors, which neither grab the device nor write to it.
+    writer: Option<Writer>,
+    grab_mode: GrabMode,
+    observer: bool,
     // The state of `read` is stored here to make it cancel safe.
     events: VecDeque<Event>,
     writing: Option<(u16, u16, i32)>,
     dropped: bool,
+    truncated: usize,
+    max_frame_events: usize,
+    // Set when the interceptor is part of an InterceptorSet.
+    pause: Option<Receiver<bool>>,
+    paused: bool,
+    // Keys reported as held by `read`, which have to be released when pausing.
+    held: KeySet,
+    // The state of `read_frame`, also kept here for cancel safety.
+    frame: Frame,
+    // Kernel timestamps of the last SYN_REPORT read and of the last frame returned by `read_frame`.
+    sync_time: Duration,
+    frame_time: Option<Duration>,
+    // Whether the frame being read is a resync one.
+    resync: bool,
+    // Effects uploaded by `apply_ff`, mapping the IDs of the requests to the ones the device allocated.
+    ff_effects: HashMap<i16, i16>,
+    // Keys held when grabbing the device again, which everyone else keeps seeing as held until released for them.
+    stuck: KeySet,
+    // The raw events injected to release them, which come back and are skipped.
+    injected: VecDeque<(u16, u16, i32)>,
 
-    _reader_handle: Handle,
-    _writer_handle: Handle,
+    _reader_handle: Option<Handle>,
+    _writer_handle: Option<Handle>,
 }
 
 impl Interceptor {
-    #[tracing::instrument(fields(path = ?self.writer.path()), skip(self))]
+    #[tracing::instrument(fields(path = ?self.writer.as_ref().and_then(Writer::path)), skip(self))]
     pub async fn read(&mut self) -> Result<Event, Error> {
-        if let Some((r#type, code, value)) = self.writing {
+        if let (Some((r#type, code, value)), Some(writer)) = (self.writing, &mut self.writer) {
             tracing::trace!("Resuming interrupted write");
 
-            self.writer.write_raw(r#type, code, value).await?;
+            writer.write_raw(r#type, code, value).await?;
             self.writing = None;
         }
 
+        self.update_paused();
+
         while !matches!(self.events.back(), Some(Event::Sync(SyncEvent::All))) {
-            let (r#type, code, value) = self.read_raw().await?;
+            let (r#type, code, value, time) = match &mut self.pause {
+                Some(pause) => {
+                    tokio::select! {
+                        result = Self::read_raw(&self.evdev) => result?,
+                        result = pause.changed() => {
+                            // The set is gone, so nothing can pause the interceptor anymore.
+                            if result.is_err() {
+                                self.pause = None;
+                            }
+
+                            self.update_paused();
+                            continue;
+                        }
+                    }
+                }
+                None => Self::read_raw(&self.evdev).await?,
+            };
+
+            if self.injected.front() == Some(&(r#type, code, value)) {
+                self.injected.pop_front();
+                continue;
+            }
+
+            // The event still went through libevdev, so the key state stays up to date for resuming.
+            if self.paused {
+                continue;
+            }
+
             let event = match r#type as _ {
                 glue::EV_REL if !self.dropped => {
                     RelAxis::from_raw(code).map(|axis| Event::Rel(RelEvent { axis, value }))
@@ -56,6 +136,9 @@ impl Interceptor {
                     _ => AbsAxis::from_raw(code).map(|axis| AbsEvent::Axis { axis, value }),
                 }
                 .map(Event::Abs),
+                // The writer repeats held keys by itself just like the device, passing these on would double the
+                // rate, or reach the local system while the key goes elsewhere.
+                glue::EV_KEY if value == 2 && self.repeat().is_some() => continue,
                 glue::EV_KEY if !self.dropped && (value == 0 || value == 1) => Key::from_raw(code)
                     .map(|key| {
                         Event::Key(KeyEvent {
@@ -63,13 +146,38 @@ impl Interceptor {
                             down: value == 1,
                         })
                     }),
+                glue::EV_MSC if !self.dropped => {
+                    Msc::from_raw(code).map(|code| Event::Msc(MscEvent { code, value }))
+                }
+                glue::EV_SW if !self.dropped => Switch::from_raw(code).map(|switch| {
+                    Event::Switch(SwitchEvent {
+                        switch,
+                        value: value != 0,
+                    })
+                }),
                 glue::EV_SYN => match code as _ {
                     glue::SYN_REPORT => {
+                        if self.truncated > 0 {
+                            tracing::warn!(
+                                "Truncated frame, dropped {} event{} over the limit of {}",
+                                self.truncated,
+                                if self.truncated == 1 { "" } else { "s" },
+                                self.max_frame_events
+                            );
+
+                            self.truncated = 0;
+                        }
+
+                        self.sync_time = time;
+
                         if self.dropped {
                             self.dropped = false;
+                            self.resync = true;
+                            self.push_resync();
                             continue;
                         }
 
+                        self.resync = false;
                         Some(Event::Sync(SyncEvent::All))
                     }
                     glue::SYN_DROPPED => {
@@ -81,6 +189,7 @@ impl Interceptor {
 
                         self.events.clear();
                         self.dropped = true;
+                        self.truncated = 0;
                         continue;
                     }
                     glue::SYN_MT_REPORT if !self.dropped => Some(Event::Sync(SyncEvent::Mt)),
@@ -90,39 +199,441 @@ impl Interceptor {
             };
 
             if let Some(event) = event {
+                // The terminating SyncEvent::All is always kept so that the frame stays well formed.
+                if self.events.len() >= self.max_frame_events
+                    && event != Event::Sync(SyncEvent::All)
+                {
+                    self.truncated += 1;
+                    continue;
+                }
+
                 self.events.push_back(event);
                 continue;
             }
 
+            // The device isn't grabbed, so the event has already been delivered.
+            let writer = match &mut self.writer {
+                Some(writer) if self.grab_mode != GrabMode::None => writer,
+                _ => continue,
+            };
+
             self.writing = Some((r#type, code, value));
-            self.writer.write_raw(r#type, code, value).await?;
+            writer.write_raw(r#type, code, value).await?;
             self.writing = None;
         }
 
-        Ok(self.events.pop_front().unwrap())
+        let event = self.events.pop_front().unwrap();
+        if let Event::Key(KeyEvent { key, down }) = event {
+            match down {
+                true => self.held.insert(key),
+                false => self.held.remove(&key),
+            };
+
+            if !down && self.stuck.remove(&key) {
+                self.release_stuck(key);
+            }
+        }
+
+        Ok(event)
+    }
+
+    // Reads a whole frame along with the time elapsed since the previous one, as reported by the kernel.
+    // This is cancel safe, the events read so far are kept until the frame is complete.
+    pub async fn read_frame(&mut self) -> Result<(Frame, Duration), Error> {
+        let mut frame = Frame::new();
+        let elapsed = self.read_into(&mut frame).await?;
+
+        Ok((frame, elapsed))
+    }
+
+    // Like `read_frame`, but replaces the contents of the given frame instead of allocating a new one.
+    // The buffers of the given frame and the one being read into are swapped, so reusing the same frame for every
+    // call doesn't allocate once both have grown large enough.
+    pub async fn read_into(&mut self, frame: &mut Frame) -> Result<Duration, Error> {
+        loop {
+            let event = self.read().await?;
+            let end = event == Event::Sync(SyncEvent::All);
+            self.frame.push(event);
+
+            if end {
+                break;
+            }
+        }
+
+        frame.clear();
+        mem::swap(frame, &mut self.frame);
+        frame.set_resync(self.resync);
+
+        let elapsed = match self.frame_time {
+            Some(frame_time) => self.sync_time.saturating_sub(frame_time),
+            None => Duration::ZERO,
+        };
+
+        self.frame_time = Some(self.sync_time);
+
+        Ok(elapsed)
+    }
+
+    // A stream of `read` results, ending after the first error.
+    pub fn events(&mut self) -> impl Stream<Item = Result<Event, Error>> + '_ {
+        stream::unfold(Some(self), |interceptor| async move {
+            let interceptor = interceptor?;
+            match interceptor.read().await {
+                Ok(event) => Some((Ok(event), Some(interceptor))),
+                Err(err) => Some((Err(err), None)),
+            }
+        })
+    }
+
+    // Like `events`, but takes ownership, so that streams of several interceptors can be combined.
+    pub fn into_stream(self) -> impl Stream<Item = Result<Event, Error>> + Send {
+        stream::unfold(Some(self), |interceptor| async move {
+            let mut interceptor = interceptor?;
+            match interceptor.read().await {
+                Ok(event) => Some((Ok(event), Some(interceptor))),
+                Err(err) => Some((Err(err), None)),
+            }
+        })
+    }
+
+    // A stream of `read_frame` results, ending after the first error.
+    // Frames following a SYN_DROPPED catch up with the device state and are marked with `Frame::is_resync`.
+    pub fn frames(&mut self) -> impl Stream<Item = Result<(Frame, Duration), Error>> + '_ {
+        stream::unfold(Some(self), |interceptor| async move {
+            let interceptor = interceptor?;
+            match interceptor.read_frame().await {
+                Ok(frame) => Some((Ok(frame), Some(interceptor))),
+                Err(err) => Some((Err(err), None)),
+            }
+        })
+    }
+
+    // Like `frames`, but also measures the report rate, see `ReportRate`.
+    pub fn measure_report_rate(
+        &mut self,
+        sample: Duration,
+    ) -> ReportRate<impl Stream<Item = Result<(Frame, Duration), Error>> + '_> {
+        ReportRate::new(self.frames(), sample)
+    }
+
+    // Brings consumers back in line with the device after the kernel dropped events.
+    // Absolute axes and switches are simply reported again, since their last reported values aren't tracked.
+    fn push_resync(&mut self) {
+        let state = self.snapshot_state();
+        let released = self.held.difference(&state.keys);
+        let pressed = state.keys.difference(&self.held);
+
+        self.events.extend(
+            released
+                .iter()
+                .map(|key| Event::Key(KeyEvent { key, down: false }))
+                .chain(
+                    pressed
+                        .iter()
+                        .map(|key| Event::Key(KeyEvent { key, down: true })),
+                )
+                .chain(state.abs.iter().map(|(axis, value)| {
+                    Event::Abs(AbsEvent::Axis {
+                        axis: *axis,
+                        value: *value,
+                    })
+                }))
+                .chain(state.switches.iter().map(|(switch, value)| {
+                    Event::Switch(SwitchEvent {
+                        switch: *switch,
+                        value: *value,
+                    })
+                })),
+        );
+        self.events.push_back(Event::Sync(SyncEvent::All));
+    }
+
+    fn update_paused(&mut self) {
+        let paused = match &mut self.pause {
+            Some(pause) => *pause.borrow_and_update(),
+            None => false,
+        };
+
+        if paused == self.paused {
+            return;
+        }
+
+        self.paused = paused;
+
+        // Whatever is left of the current frame is discarded, the synthesized frame below terminates it.
+        self.events.clear();
+
+        let keys = if paused {
+            self.held.iter().collect::<Vec<_>>()
+        } else {
+            self.snapshot_state().keys.iter().collect()
+        };
+
+        self.events.extend(
+            keys.into_iter()
+                .map(|key| Event::Key(KeyEvent { key, down: !paused })),
+        );
+        self.events.push_back(Event::Sync(SyncEvent::All));
     }
 
+    // Writing to a passive interceptor does nothing, since the system receives the events of the device directly.
     pub async fn write(&mut self, event: &Event) -> Result<(), Error> {
-        self.writer.write(event).await
+        match &mut self.writer {
+            Some(writer) if self.grab_mode != GrabMode::None => writer.write(event).await,
+            // Released by `set_grab`, the events of the device reach the system on their own.
+            Some(_) => Ok(()),
+            None if self.observer => Err(Error::new(
+                ErrorKind::Unsupported,
+                "Observers can not be written to",
+            )),
+            None => Ok(()),
+        }
+    }
+
+    // Sets the LED on the device itself, unlike `write` which only reaches the system.
+    pub fn set_led(&mut self, event: &LedEvent) -> Result<(), Error> {
+        if self.observer {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Observers can not be written to",
+            ));
+        }
+
+        let code = match event.led.to_raw() {
+            Some(code) => code,
+            None => return Ok(()),
+        };
+
+        let value = match event.state {
+            true => glue::libevdev_led_value_LIBEVDEV_LED_ON,
+            false => glue::libevdev_led_value_LIBEVDEV_LED_OFF,
+        };
+
+        let ret =
+            unsafe { glue::libevdev_kernel_set_led_value(self.evdev.as_ptr(), code as _, value) };
+        if ret < 0 {
+            return Err(Error::from_raw_os_error(-ret));
+        }
+
+        Ok(())
+    }
+
+    // Carries out a force feedback request made to another device, typically a writer recreating this one.
+    // Requests for effects which weren't uploaded here first are ignored. The kernel erases the effects
+    // once the interceptor is dropped.
+    pub fn apply_ff(&mut self, request: &FfRequest) -> Result<(), Error> {
+        if self.observer {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Observers can not be written to",
+            ));
+        }
+
+        let fd = self.evdev.file().unwrap().as_raw_fd();
+
+        match *request {
+            FfRequest::Upload { id, effect } => {
+                // Uploading under an ID already allocated updates the effect instead of creating a new one.
+                let mut raw = effect.to_raw(self.ff_effects.get(&id).copied().unwrap_or(-1));
+
+                let ret = unsafe { libc::ioctl(fd, EVIOCSFF as _, &mut raw) };
+                if ret < 0 {
+                    return Err(Error::last_os_error());
+                }
+
+                self.ff_effects.insert(id, raw.id);
+            }
+            FfRequest::Erase { id } => {
+                let id = match self.ff_effects.remove(&id) {
+                    Some(id) => id,
+                    None => return Ok(()),
+                };
+
+                let ret = unsafe { libc::ioctl(fd, EVIOCRMFF as _, id as c_int) };
+                if ret < 0 {
+                    return Err(Error::last_os_error());
+                }
+            }
+            FfRequest::Play { id, count } => {
+                if let Some(id) = self.ff_effects.get(&id) {
+                    write_event(fd, glue::EV_FF as _, *id as _, count)?;
+                }
+            }
+            FfRequest::Gain(gain) => {
+                write_event(fd, glue::EV_FF as _, glue::FF_GAIN as _, gain as _)?
+            }
+        }
+
+        Ok(())
+    }
+
+    // The path of the device node the interceptor reads from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // The mode actually achieved, either GrabMode::Exclusive or GrabMode::None.
+    pub fn grab_mode(&self) -> GrabMode {
+        self.grab_mode
+    }
+
+    // Releases or grabs the device again without reopening it, for example while the local machine is in use.
+    // Only interceptors which grabbed the device when opening it can do this, the others have no writer
+    // to hand the events back to the system with. Events queued by the kernel before releasing are still read.
+    // Keys held through `write` stay held on the writer, so they should be released before releasing the grab.
+    // Keys held when grabbing again are released for everyone else as soon as they are released.
+    pub fn set_grab(&mut self, grab: bool) -> Result<(), Error> {
+        if self.writer.is_none() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Only interceptors which grabbed the device can change the grab",
+            ));
+        }
+
+        let mode = match grab {
+            true => glue::libevdev_grab_mode_LIBEVDEV_GRAB,
+            false => glue::libevdev_grab_mode_LIBEVDEV_UNGRAB,
+        };
+
+        self.grab(mode)?;
+
+        // Their releases reach only the interceptor from now on.
+        self.stuck = match (grab, self.grab_mode) {
+            (true, GrabMode::None) => DeviceState::from_evdev(&self.evdev).keys,
+            (true, _) => self.stuck,
+            (false, _) => KeySet::new(),
+        };
+
+        self.grab_mode = match grab {
+            true => GrabMode::Exclusive,
+            false => GrabMode::None,
+        };
+
+        Ok(())
+    }
+
+    fn grab(&mut self, mode: glue::libevdev_grab_mode) -> Result<(), Error> {
+        let ret = unsafe { glue::libevdev_grab(self.evdev.as_ptr(), mode) };
+        if ret == -libc::EBUSY {
+            return Err(Error::new(
+                ErrorKind::Other,
+                "Device is grabbed by another process",
+            ));
+        }
+
+        if ret < 0 {
+            return Err(Error::from_raw_os_error(-ret));
+        }
+
+        Ok(())
     }
 
-    pub fn name(&self) -> &CStr {
+    // Lets go of the grab for as long as it takes to inject a press and a release of the key, which the kernel
+    // only passes on if it's no longer held. Input arriving in the meantime reaches everyone.
+    fn release_stuck(&mut self, key: Key) {
+        let code = match key.to_raw() {
+            Some(code) => code,
+            None => return,
+        };
+
+        let events = [
+            (glue::EV_KEY as u16, code, 1),
+            (glue::EV_KEY as _, code, 0),
+            (glue::EV_SYN as _, glue::SYN_REPORT as _, 0),
+        ];
+
+        let fd = self.evdev.file().unwrap().as_raw_fd();
+
+        if let Err(err) = self.grab(glue::libevdev_grab_mode_LIBEVDEV_UNGRAB) {
+            tracing::warn!("Failed to release {:?} for other readers: {}", key, err);
+            return;
+        }
+
+        for (r#type, code, value) in events {
+            match write_event(fd, r#type, code, value) {
+                Ok(()) => self.injected.push_back((r#type, code, value)),
+                Err(err) => {
+                    tracing::warn!("Failed to release {:?} for other readers: {}", key, err);
+                    break;
+                }
+            }
+        }
+
+        if let Err(err) = self.grab(glue::libevdev_grab_mode_LIBEVDEV_GRAB) {
+            tracing::error!(
+                "Failed to grab the device again, its input now reaches everyone: {}",
+                err
+            );
+
+            self.grab_mode = GrabMode::None;
+            self.stuck.clear();
+        }
+    }
+
+    pub fn set_max_frame_events(&mut self, max: usize) {
+        self.max_frame_events = max;
+    }
+
+    pub(crate) fn evdev(&self) -> &Evdev {
+        &self.evdev
+    }
+
+    // What the device was when it was opened.
+    pub fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    // Empty for devices without a name, bytes that aren't valid UTF-8 are replaced. See `name_cstr` for the name
+    // exactly as the device reports it.
+    pub fn name(&self) -> &str {
+        &self.info.name
+    }
+
+    // None for devices without a name.
+    pub fn name_cstr(&self) -> Option<&CStr> {
         let name = unsafe { glue::libevdev_get_name(self.evdev.as_ptr()) };
-        let name = unsafe { CStr::from_ptr(name) };
+        if name.is_null() {
+            return None;
+        }
 
-        name
+        Some(unsafe { CStr::from_ptr(name) })
+    }
+
+    pub fn bustype(&self) -> u16 {
+        self.info.bus_type
     }
 
     pub fn vendor(&self) -> u16 {
-        unsafe { glue::libevdev_get_id_vendor(self.evdev.as_ptr()) as _ }
+        self.info.vendor
     }
 
     pub fn product(&self) -> u16 {
-        unsafe { glue::libevdev_get_id_product(self.evdev.as_ptr()) as _ }
+        self.info.product
     }
 
     pub fn version(&self) -> u16 {
-        unsafe { glue::libevdev_get_id_version(self.evdev.as_ptr()) as _ }
+        self.info.version
+    }
+
+    // Everything `WriterBuilder::from_capabilities` needs to recreate the device.
+    pub fn capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            // Names are recreated as they are, even if they aren't valid UTF-8.
+            name: self.name_cstr().map(CStr::to_owned).unwrap_or_default(),
+            vendor: self.vendor(),
+            product: self.product(),
+            version: self.version(),
+            rel: self.rel().collect(),
+            abs: self.abs().collect(),
+            keys: self.key().collect(),
+            syn: self.syn_codes().collect(),
+            msc: self.msc_codes().collect(),
+            leds: self.led_codes().collect(),
+            ff: self.ff_codes().collect(),
+            switches: self.switch_codes().collect(),
+            props: self.props().collect(),
+            repeat: self.repeat(),
+        }
     }
 
     pub fn rel(&self) -> RelCaps {
@@ -137,15 +648,98 @@ impl Interceptor {
         KeyCaps::new(self)
     }
 
-    async fn read_raw(&mut self) -> Result<(u16, u16, i32), Error> {
-        let file = self.evdev.file().unwrap();
+    // Some devices enable SYN_MT_REPORT and some don't, userspace can misdetect the device if this isn't mirrored.
+    pub fn syn_codes(&self) -> impl Iterator<Item = SyncEvent> + '_ {
+        (0..glue::SYN_CNT)
+            .filter(move |code| unsafe {
+                glue::libevdev_has_event_code(self.evdev.as_ptr(), glue::EV_SYN, *code) == 1
+            })
+            .filter_map(|code| SyncEvent::from_raw(code as _))
+    }
+
+    pub fn msc_codes(&self) -> impl Iterator<Item = Msc> + '_ {
+        (0..glue::MSC_CNT)
+            .filter(move |code| unsafe {
+                glue::libevdev_has_event_code(self.evdev.as_ptr(), glue::EV_MSC, *code) == 1
+            })
+            .filter_map(|code| Msc::from_raw(code as _))
+    }
+
+    pub fn led_codes(&self) -> impl Iterator<Item = Led> + '_ {
+        (0..glue::LED_CNT)
+            .filter(move |code| unsafe {
+                glue::libevdev_has_event_code(self.evdev.as_ptr(), glue::EV_LED, *code) == 1
+            })
+            .filter_map(|code| Led::from_raw(code as _))
+    }
+
+    pub fn ff_codes(&self) -> impl Iterator<Item = FfEffectType> + '_ {
+        (0..glue::FF_CNT)
+            .filter(move |code| unsafe {
+                glue::libevdev_has_event_code(self.evdev.as_ptr(), glue::EV_FF, *code) == 1
+            })
+            .filter_map(|code| FfEffectType::from_raw(code as _))
+    }
+
+    pub fn switch_codes(&self) -> impl Iterator<Item = Switch> + '_ {
+        (0..glue::SW_CNT)
+            .filter(move |code| unsafe {
+                glue::libevdev_has_event_code(self.evdev.as_ptr(), glue::EV_SW, *code) == 1
+            })
+            .filter_map(|code| Switch::from_raw(code as _))
+    }
+
+    pub fn has_prop(&self, prop: InputProp) -> bool {
+        match prop.to_raw() {
+            Some(prop) => unsafe {
+                glue::libevdev_has_property(self.evdev.as_ptr(), prop as _) == 1
+            },
+            None => false,
+        }
+    }
+
+    pub fn props(&self) -> impl Iterator<Item = InputProp> + '_ {
+        (0..glue::INPUT_PROP_CNT)
+            .filter_map(|prop| InputProp::from_raw(prop as _))
+            .filter(move |prop| self.has_prop(*prop))
+    }
+
+    // The auto repeat delay and period in milliseconds, None if the device doesn't repeat.
+    pub fn repeat(&self) -> Option<(i32, i32)> {
+        let mut delay = 0;
+        let mut period = 0;
+
+        let ret =
+            unsafe { glue::libevdev_get_repeat(self.evdev.as_ptr(), &mut delay, &mut period) };
+        if ret < 0 {
+            return None;
+        }
+
+        Some((delay, period))
+    }
+
+    // Returns None for wired devices and devices which don't report their battery.
+    pub fn battery(&self) -> Option<BatteryInfo> {
+        let metadata = self.evdev.file()?.get_ref().metadata().ok()?;
+        let rdev = metadata.rdev();
+
+        battery::read(unsafe { libc::major(rdev) }, unsafe { libc::minor(rdev) })
+    }
+
+    // Reflects every event read from the device so far, including those still buffered by `read`.
+    pub fn snapshot_state(&self) -> DeviceState {
+        DeviceState::from_evdev(&self.evdev)
+    }
+
+    async fn read_raw(evdev: &Evdev) -> Result<(u16, u16, i32, Duration), Error> {
+        let file = evdev.file().unwrap();
 
         loop {
             let result = file.readable().await?.try_io(|_| {
                 let mut event = MaybeUninit::uninit();
                 let ret = unsafe {
                     glue::libevdev_next_event(
-                        self.evdev.as_ptr(),
+                        evdev.as_ptr(),
                         glue::libevdev_read_flag_LIBEVDEV_READ_FLAG_NORMAL,
                         event.as_mut_ptr(),
                     )
@@ -166,32 +760,51 @@ impl Interceptor {
                 }
 
                 let event = unsafe { event.assume_init() };
-                Ok((event.type_, event.code, event.value))
+                let time = Duration::new(event.time.tv_sec as _, event.time.tv_usec as u32 * 1000);
+
+                Ok((event.type_, event.code, event.value, time))
             });
 
             match result {
                 Ok(result) => return result,
-                Err(_) => continue, // This means it would block.
+                // It would block. try_io cleared the readiness, so waiting again doesn't return right away.
+                Err(_) => continue,
             }
         }
     }
 
-    #[tracing::instrument(skip(registry))]
-    pub(crate) async fn open(path: &Path, registry: &Registry) -> Result<Self, OpenError> {
+    // Opens the device purely for observation: it is opened read only, never grabbed and never written to,
+    // so other consumers of the device are not affected in any way. Unlike the devices returned by the monitor,
+    // any device can be observed, including switches and the virtual devices created by rkvm itself.
+    pub async fn open_observer(path: &Path) -> Result<Self, Error> {
+        let evdev = Evdev::open_read_only(path).await?;
+        let metadata = evdev.file().unwrap().get_ref().metadata()?;
+        let info = device_info(path, &evdev, &metadata);
+
+        let mut interceptor = Self::from_parts(path, info, evdev, None, None, None);
+        interceptor.observer = true;
+
+        Ok(interceptor)
+    }
+
+    #[tracing::instrument(skip(registry, accept))]
+    pub(crate) async fn open(
+        path: &Path,
+        registry: &Registry,
+        grab_mode: GrabMode,
+        accept: impl FnOnce(&DeviceInfo) -> bool,
+    ) -> Result<Self, OpenError> {
         let evdev = Evdev::open(path).await?;
         let metadata = evdev.file().unwrap().get_ref().metadata()?;
 
         let reader_handle = registry
             .register(Entry::from_metadata(&metadata))
-            .ok_or(OpenError::NotAppliable)?;
-
-        // "Upon binding to a device or resuming from suspend, a driver must report
-        // the current switch state. This ensures that the device, kernel, and userspace
-        // state is in sync."
-        // We have no way of knowing that.
-        let sw = unsafe { glue::libevdev_has_event_type(evdev.as_ptr(), glue::EV_SW) };
-        if sw == 1 {
-            return Err(OpenError::NotAppliable);
+            .ok_or(OpenError::NotAppliable(NotAppliableReason::Registered))?;
+
+        // Decided before the device is modified or grabbed in any way, the bus type is overwritten below.
+        let info = device_info(path, &evdev, &metadata);
+        if !accept(&info) {
+            return Err(OpenError::Rejected);
         }
 
         // Some buggy kernels can report nonsense abs info, so check for it and disable the axes.
@@ -228,14 +841,42 @@ impl Interceptor {
             glue::libevdev_set_id_bustype(evdev.as_ptr(), glue::BUS_VIRTUAL as _);
         }
 
-        let ret =
-            unsafe { glue::libevdev_grab(evdev.as_ptr(), glue::libevdev_grab_mode_LIBEVDEV_GRAB) };
+        let ret = match grab_mode {
+            GrabMode::None => {
+                return Ok(Self::from_parts(
+                    path,
+                    info,
+                    evdev,
+                    None,
+                    Some(reader_handle),
+                    None,
+                ));
+            }
+            GrabMode::Exclusive | GrabMode::BestEffort => unsafe {
+                glue::libevdev_grab(evdev.as_ptr(), glue::libevdev_grab_mode_LIBEVDEV_GRAB)
+            },
+        };
 
         if ret < 0 {
             // We do not use ErrorKind::ResourceBusy because it is a nightly-only API.
             let err = if ret == -libc::EBUSY {
-                tracing::info!("Ignored {:?} because it is busy and can not be grabbed", path);
-                OpenError::NotAppliable
+                if grab_mode == GrabMode::BestEffort {
+                    tracing::warn!(
+                        "Could not grab {:?} because it is busy, falling back to passive mode",
+                        path
+                    );
+
+                    return Ok(Self::from_parts(
+                        path,
+                        info,
+                        evdev,
+                        None,
+                        Some(reader_handle),
+                        None,
+                    ));
+                }
+
+                OpenError::Grabbed { name: info.name }
             } else {
                 Error::from_raw_os_error(-ret).into()
             };
@@ -243,35 +884,195 @@ impl Interceptor {
             return Err(err);
         }
 
-        let writer = Writer::from_evdev(&evdev).await?;
-        let path = writer
+        let mut writer = Writer::from_evdev(&evdev).await?;
+        let writer_path = writer
             .path()
             .ok_or_else(|| Error::new(ErrorKind::Other, "No syspath for writer"))?;
 
-        let metadata = fs::metadata(path)?;
+        let metadata = fs::metadata(writer_path)?;
         let writer_handle = registry
             .register(Entry::from_metadata(&metadata))
             .ok_or_else(|| Error::new(ErrorKind::Other, "Writer already registered"))?;
 
-        Ok(Self {
+        // The switches of the writer start out off, which could for example make a closed lid look open.
+        // libevdev read their actual state when opening the device.
+        let switches = DeviceState::from_evdev(&evdev)
+            .switches
+            .into_iter()
+            .filter(|(_, value)| *value)
+            .map(|(switch, value)| Event::Switch(SwitchEvent { switch, value }))
+            .collect::<Vec<_>>();
+
+        writer.write_frame(&switches.into()).await?;
+
+        Ok(Self::from_parts(
+            path,
+            info,
             evdev,
+            Some(writer),
+            Some(reader_handle),
+            Some(writer_handle),
+        ))
+    }
+
+    fn from_parts(
+        path: &Path,
+        info: DeviceInfo,
+        evdev: Evdev,
+        writer: Option<Writer>,
+        reader_handle: Option<Handle>,
+        writer_handle: Option<Handle>,
+    ) -> Self {
+        Self {
+            path: path.to_owned(),
+            info,
+            evdev,
+            grab_mode: match writer {
+                Some(_) => GrabMode::Exclusive,
+                None => GrabMode::None,
+            },
+            observer: false,
             writer,
             events: VecDeque::new(),
             dropped: false,
+            truncated: 0,
+            max_frame_events: MAX_FRAME_EVENTS,
             writing: None,
+            pause: None,
+            paused: false,
+            held: KeySet::new(),
+            frame: Frame::new(),
+            sync_time: Duration::ZERO,
+            frame_time: None,
+            resync: false,
+            ff_effects: HashMap::new(),
+            stuck: KeySet::new(),
+            injected: VecDeque::new(),
 
             _reader_handle: reader_handle,
             _writer_handle: writer_handle,
-        })
+        }
     }
 }
 
 unsafe impl Send for Interceptor {}
 
+// Playing effects and setting the gain are done by writing EV_FF events to the device, the kernel passes
+// other events on as if the device had reported them.
+fn write_event(fd: c_int, r#type: u16, code: u16, value: i32) -> Result<(), Error> {
+    let mut event: input_event = unsafe { mem::zeroed() };
+    event.type_ = r#type;
+    event.code = code;
+    event.value = value;
+
+    let size = mem::size_of::<input_event>();
+    let ret = unsafe { libc::write(fd, &event as *const _ as _, size) };
+    if ret < 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum GrabMode {
+    // Grab the device, skipping it if that's not possible.
+    #[default]
+    Exclusive,
+    // Grab the device if possible, otherwise read it passively.
+    BestEffort,
+    // Read the device passively, its events keep reaching the system.
+    None,
+}
+
+fn device_info(path: &Path, evdev: &Evdev, metadata: &fs::Metadata) -> DeviceInfo {
+    let name = unsafe { glue::libevdev_get_name(evdev.as_ptr()) };
+    let name = if name.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(name) }
+            .to_string_lossy()
+            .into_owned()
+    };
+
+    let rdev = metadata.rdev();
+    let syspath = format!(
+        "/sys/dev/char/{}:{}",
+        unsafe { libc::major(rdev) },
+        unsafe { libc::minor(rdev) }
+    );
+
+    // The input device is the parent of the event device.
+    let syspath = fs::canonicalize(syspath)
+        .ok()
+        .and_then(|syspath| syspath.parent().map(Path::to_owned))
+        .unwrap_or_default();
+
+    unsafe {
+        DeviceInfo {
+            name,
+            path: Some(path.to_owned()),
+            syspath,
+            bus_type: glue::libevdev_get_id_bustype(evdev.as_ptr()) as _,
+            vendor: glue::libevdev_get_id_vendor(evdev.as_ptr()) as _,
+            product: glue::libevdev_get_id_product(evdev.as_ptr()) as _,
+            version: glue::libevdev_get_id_version(evdev.as_ptr()) as _,
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 pub(crate) enum OpenError {
-    #[error("Not appliable")]
-    NotAppliable,
+    #[error("Not appliable: {0}")]
+    NotAppliable(NotAppliableReason),
+    #[error("Rejected by the filter")]
+    Rejected,
+    // Some other process, such as keyd or another instance of rkvm, holds a grab on the device.
+    #[error("{name:?} is grabbed by another process")]
+    Grabbed { name: String },
     #[error(transparent)]
     Io(#[from] Error),
 }
+
+#[derive(Error, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum NotAppliableReason {
+    // Either opened already or one of the devices created by rkvm.
+    #[error("already registered")]
+    Registered,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::key::Keyboard;
+    use crate::writer::WriterBuilder;
+
+    // Needs access to /dev/uinput and to the device node created for the writer.
+    #[tokio::test]
+    #[ignore]
+    async fn identity() {
+        let name = CStr::from_bytes_with_nul(b"rkvm identity test\0").unwrap();
+        let writer = WriterBuilder::new()
+            .unwrap()
+            .name(name)
+            .vendor(0x1234)
+            .product(0x5678)
+            .version(3)
+            .key([Key::Key(Keyboard::A)])
+            .unwrap()
+            .build_and_wait(Duration::from_secs(5))
+            .await
+            .unwrap();
+
+        let interceptor = Interceptor::open_observer(writer.path().unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(interceptor.name(), "rkvm identity test");
+        assert_eq!(interceptor.name_cstr(), Some(name));
+        assert_eq!(interceptor.vendor(), 0x1234);
+        assert_eq!(interceptor.product(), 0x5678);
+        assert_eq!(interceptor.version(), 3);
+        assert_eq!(interceptor.info().path.as_deref(), writer.path());
+    }
+}
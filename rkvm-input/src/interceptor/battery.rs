@@ -0,0 +1,66 @@
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatteryInfo {
+    // In percent, not all devices report it.
+    pub capacity: Option<u8>,
+    pub status: BatteryStatus,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatteryStatus {
+    Charging,
+    Discharging,
+    NotCharging,
+    Full,
+    Unknown,
+}
+
+impl BatteryStatus {
+    fn from_sysfs(status: &str) -> Self {
+        match status {
+            "Charging" => Self::Charging,
+            "Discharging" => Self::Discharging,
+            "Not charging" => Self::NotCharging,
+            "Full" => Self::Full,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+// Wireless devices expose their battery as a power_supply node of one of the ancestors of the event device,
+// typically the HID device. Wired devices don't have one.
+pub(super) fn read(major: u32, minor: u32) -> Option<BatteryInfo> {
+    let path = format!("/sys/dev/char/{}:{}", major, minor);
+    let path = fs::canonicalize(path).ok()?;
+
+    path.ancestors()
+        .take_while(|path| path.starts_with("/sys/devices") && *path != Path::new("/sys/devices"))
+        .find_map(|path| {
+            fs::read_dir(path.join("power_supply"))
+                .ok()?
+                .filter_map(Result::ok)
+                .find_map(|supply| read_supply(&supply.path()))
+        })
+}
+
+fn read_supply(path: &Path) -> Option<BatteryInfo> {
+    let read = |name| fs::read_to_string(path.join(name)).ok();
+
+    if read("type")?.trim() != "Battery" {
+        return None;
+    }
+
+    // The laptop battery is a system supply, just in case it ever shows up here.
+    if matches!(read("scope"), Some(scope) if scope.trim() == "System") {
+        return None;
+    }
+
+    let capacity = read("capacity").and_then(|capacity| capacity.trim().parse().ok());
+    let status = read("status")
+        .map(|status| BatteryStatus::from_sysfs(status.trim()))
+        .unwrap_or(BatteryStatus::Unknown);
+
+    Some(BatteryInfo { capacity, status })
+}
@@ -0,0 +1,48 @@
+use crate::interceptor::Interceptor;
+
+use tokio::sync::watch::{self, Receiver, Sender};
+
+// Pauses and resumes a group of interceptors at once. All of them observe the same flag,
+// so there is no window in which some devices are paused and others still deliver events.
+//
+// While paused, interceptors release every key they have reported as held and then discard all events.
+// On resume, they press the keys that are still physically held.
+pub struct InterceptorSet {
+    sender: Sender<bool>,
+    // Kept so that the flag can be changed with no interceptors in the set.
+    _receiver: Receiver<bool>,
+}
+
+impl InterceptorSet {
+    pub fn new() -> Self {
+        let (sender, receiver) = watch::channel(false);
+
+        Self {
+            sender,
+            _receiver: receiver,
+        }
+    }
+
+    // The interceptor starts out in the current state of the set.
+    pub fn add(&self, interceptor: &mut Interceptor) {
+        interceptor.pause = Some(self.sender.subscribe());
+    }
+
+    pub fn pause_all(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn resume_all(&self) {
+        self.sender.send_replace(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.sender.borrow()
+    }
+}
+
+impl Default for InterceptorSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
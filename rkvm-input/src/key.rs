@@ -5,6 +5,7 @@ pub use button::Button;
 pub use keyboard::Keyboard;
 
 use crate::convert::Convert;
+use crate::glue;
 
 use serde::{Deserialize, Serialize};
 
@@ -18,6 +19,8 @@ pub struct KeyEvent {
 pub enum Key {
     Key(Keyboard),
     Button(Button),
+    // Any other code below KEY_CNT, so that keys missing above are still forwarded.
+    Other(u16),
 }
 
 impl Convert for Key {
@@ -32,6 +35,10 @@ impl Convert for Key {
             return Some(Self::Button(button));
         }
 
+        if code < glue::KEY_CNT as u16 {
+            return Some(Self::Other(code));
+        }
+
         None
     }
 
@@ -39,6 +46,8 @@ impl Convert for Key {
         match self {
             Self::Key(key) => key.to_raw(),
             Self::Button(button) => button.to_raw(),
+            Self::Other(code) if *code < glue::KEY_CNT as u16 => Some(*code),
+            Self::Other(_) => None,
         }
     }
 }
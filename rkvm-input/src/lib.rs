@@ -1,9 +1,21 @@
 pub mod abs;
+pub mod capabilities;
+pub mod diagnostics;
 pub mod event;
+pub mod ff;
+pub mod frame;
 pub mod interceptor;
 pub mod key;
+pub mod led;
 pub mod monitor;
+pub mod msc;
+pub mod profile;
+pub mod prop;
 pub mod rel;
+pub mod rep;
+pub mod set;
+pub mod state;
+pub mod switch;
 pub mod sync;
 pub mod writer;
 
@@ -1,32 +1,138 @@
-use crate::interceptor::{Interceptor, OpenError};
+use crate::diagnostics::{DeviceInfo, MatchSpec};
+use crate::interceptor::{GrabMode, Interceptor, NotAppliableReason, OpenError};
 use crate::registry::Registry;
 
 use futures::StreamExt;
-use inotify::{Inotify, WatchMask};
+use inotify::{EventMask, Inotify, WatchMask};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::ffi::OsStr;
+use std::fs::canonicalize;
+use std::future;
 use std::io::{Error, ErrorKind};
 use std::path::{Path, PathBuf};
-use std::collections::HashSet;
-use std::fs::canonicalize;
-use tokio::fs;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::fs::{self, OpenOptions};
 use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::time;
 
 const EVENT_PATH: &str = "/dev/input";
 
+// Skips with the same reason are logged at most once per this interval, so that churning devices don't flood the log.
+const SKIP_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+// How often the monitor checks again while it lacks the permissions to open event devices.
+const PERMISSION_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+// How long a device that can't be opened for lack of permissions is waited on for udev to change them.
+const ATTRIB_TIMEOUT: Duration = Duration::from_secs(5);
+
+const GRAB_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
 pub struct Monitor {
-    receiver: Receiver<Result<Interceptor, Error>>,
+    receiver: Receiver<Result<MonitorEvent, Error>>,
+    stats: Arc<Mutex<MonitorStats>>,
+}
+
+// Monitor events are rare and short lived, so there is no point in boxing the interceptor.
+#[allow(clippy::large_enum_variant)]
+pub enum MonitorEvent {
+    Added(Interceptor),
+    // The device node of a previously added device was removed. Its interceptor fails on the next read or write.
+    Removed(PathBuf),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MonitorStats {
+    // Event devices discovered, including the skipped ones.
+    pub seen: usize,
+    pub opened: usize,
+    pub failed: usize,
+    pub skipped: HashMap<SkipReason, usize>,
+}
+
+#[derive(Error, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SkipReason {
+    #[error("not among the input device paths")]
+    Filtered,
+    #[error("rejected by the filter")]
+    Rejected,
+    #[error("grabbed by another process")]
+    Grabbed,
+    #[error(transparent)]
+    NotAppliable(#[from] NotAppliableReason),
+}
+
+// What to do with devices some other process, such as keyd or another instance of rkvm, has grabbed.
+// Only applies to GrabMode::Exclusive, the others fall back to reading such devices passively.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GrabbedPolicy {
+    // Try again every so often, picking the device up once the other process lets go of it.
+    Retry(Duration),
+    Skip,
+    // Stop the monitor with an error.
+    Fail,
+}
+
+impl Default for GrabbedPolicy {
+    fn default() -> Self {
+        Self::Retry(GRAB_RETRY_INTERVAL)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum MonitorInitError {
+    #[error("The input device directory does not exist, either the kernel lacks evdev support or it is not available in this environment")]
+    Missing,
+    #[error("Permission denied accessing the input devices, run as root or add the user to the input group (which takes effect on the next login)")]
+    PermissionDenied,
+    #[error(transparent)]
+    Io(#[from] Error),
+}
+
+impl From<MonitorInitError> for Error {
+    fn from(err: MonitorInitError) -> Self {
+        match err {
+            MonitorInitError::Missing => Error::new(ErrorKind::NotFound, err),
+            MonitorInitError::PermissionDenied => Error::new(ErrorKind::PermissionDenied, err),
+            MonitorInitError::Io(err) => err,
+        }
+    }
 }
 
 impl Monitor {
+    // Checks whether the event devices can be opened at all, so that callers can explain what is wrong
+    // instead of failing on the first device. The monitor itself keeps waiting while permission is denied.
+    pub async fn preflight() -> Result<(), MonitorInitError> {
+        preflight(Path::new(EVENT_PATH)).await
+    }
+
     pub fn new(input_device_paths: &HashSet<String>) -> Self {
-        let (sender, receiver) = mpsc::channel(1);
-        let absolute_input_device_paths = canonicalize_input_device_paths(input_device_paths);
-        tokio::spawn(monitor(sender, absolute_input_device_paths));
+        Self::builder()
+            .input_device_paths(input_device_paths)
+            .build()
+    }
+
+    // Only hands out the devices the filter accepts, see `MonitorBuilder::filter`.
+    pub fn with_filter(filter: impl Fn(&DeviceInfo) -> bool + Send + Sync + 'static) -> Self {
+        Self::builder().filter(filter).build()
+    }
+
+    // Watches the given directory instead of /dev/input, for example one populated with fake devices.
+    pub fn with_path(path: impl AsRef<Path>) -> Self {
+        Self::builder().path(path).build()
+    }
+
+    pub fn builder() -> MonitorBuilder {
+        MonitorBuilder::new()
+    }
 
-        Self { receiver }
+    pub fn stats(&self) -> MonitorStats {
+        self.stats.lock().unwrap().clone()
     }
 
-    pub async fn read(&mut self) -> Result<Interceptor, Error> {
+    pub async fn read(&mut self) -> Result<MonitorEvent, Error> {
         self.receiver
             .recv()
             .await
@@ -34,33 +140,235 @@ impl Monitor {
     }
 }
 
-async fn monitor(sender: Sender<Result<Interceptor, Error>>, input_device_paths: HashSet<String>) {
+#[derive(Default)]
+pub struct MonitorBuilder {
+    input_device_paths: HashSet<String>,
+    grab_mode: GrabMode,
+    grab_modes: HashMap<String, GrabMode>,
+    grabbed: GrabbedPolicy,
+    path: Option<PathBuf>,
+    filter: Option<Filter>,
+    allow: Vec<MatchSpec>,
+    deny: Vec<MatchSpec>,
+}
+
+type Filter = Box<dyn Fn(&DeviceInfo) -> bool + Send + Sync>;
+
+impl MonitorBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Only these devices are opened, all are if empty.
+    pub fn input_device_paths(mut self, paths: &HashSet<String>) -> Self {
+        self.input_device_paths = paths.clone();
+        self
+    }
+
+    pub fn grab_mode(mut self, mode: GrabMode) -> Self {
+        self.grab_mode = mode;
+        self
+    }
+
+    // Overrides the grab mode for a single device.
+    pub fn device_grab_mode(mut self, path: &str, mode: GrabMode) -> Self {
+        self.grab_modes.insert(path.to_owned(), mode);
+        self
+    }
+
+    pub fn grabbed(mut self, policy: GrabbedPolicy) -> Self {
+        self.grabbed = policy;
+        self
+    }
+
+    // The directory to watch for event devices, /dev/input by default.
+    pub fn path(mut self, path: impl AsRef<Path>) -> Self {
+        self.path = Some(path.as_ref().to_owned());
+        self
+    }
+
+    // Called for every device right after it has been opened, devices for which it returns false
+    // are closed again without ever being grabbed.
+    pub fn filter(mut self, filter: impl Fn(&DeviceInfo) -> bool + Send + Sync + 'static) -> Self {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    // Only devices matching one of the specs are opened, all are if there are none.
+    pub fn allow(mut self, specs: impl IntoIterator<Item = MatchSpec>) -> Self {
+        self.allow.extend(specs);
+        self
+    }
+
+    // Devices matching one of the specs are never opened, this takes precedence over `allow`.
+    pub fn deny(mut self, specs: impl IntoIterator<Item = MatchSpec>) -> Self {
+        self.deny.extend(specs);
+        self
+    }
+
+    pub fn build(self) -> Monitor {
+        let (sender, receiver) = mpsc::channel(1);
+        let stats = Arc::new(Mutex::new(MonitorStats::default()));
+
+        let options = Options {
+            path: self.path.unwrap_or_else(|| EVENT_PATH.into()),
+            filter: self.filter,
+            allow: self.allow,
+            deny: self.deny,
+            input_device_paths: canonicalize_input_device_paths(&self.input_device_paths),
+            grab_mode: self.grab_mode,
+            grabbed: self.grabbed,
+            grab_modes: self
+                .grab_modes
+                .into_iter()
+                .filter_map(|(path, mode)| match canonicalize(&path) {
+                    Ok(path) => Some((path, mode)),
+                    Err(err) => {
+                        tracing::error!("Failed to canonicalize a path: {}", err);
+                        None
+                    }
+                })
+                .collect(),
+        };
+
+        tokio::spawn(monitor(sender, options, stats.clone()));
+
+        Monitor { receiver, stats }
+    }
+}
+
+struct Options {
+    path: PathBuf,
+    filter: Option<Filter>,
+    allow: Vec<MatchSpec>,
+    deny: Vec<MatchSpec>,
+    input_device_paths: HashSet<String>,
+    grab_mode: GrabMode,
+    grab_modes: HashMap<PathBuf, GrabMode>,
+    grabbed: GrabbedPolicy,
+}
+
+impl Options {
+    fn accepts(&self, device: &DeviceInfo) -> bool {
+        let allowed = self.allow.is_empty() || self.allow.iter().any(|spec| spec.matches(device));
+        let denied = self.deny.iter().any(|spec| spec.matches(device));
+
+        let accepted = match &self.filter {
+            Some(filter) => filter(device),
+            None => true,
+        };
+
+        allowed && !denied && accepted
+    }
+}
+
+async fn monitor(
+    sender: Sender<Result<MonitorEvent, Error>>,
+    options: Options,
+    stats: Arc<Mutex<MonitorStats>>,
+) {
+    let update = |f: &dyn Fn(&mut MonitorStats)| f(&mut stats.lock().unwrap());
+
     let run = async {
         let registry = Registry::new();
+        let mut skip_log = SkipLog::default();
 
-        let mut read_dir = fs::read_dir(EVENT_PATH).await?;
+        // Permissions might be granted shortly, for example by udev setting up the device nodes.
+        let mut warned = false;
+        loop {
+            match preflight(&options.path).await {
+                Ok(()) => break,
+                Err(MonitorInitError::PermissionDenied) => {
+                    if !warned {
+                        tracing::warn!("{}, retrying", MonitorInitError::PermissionDenied);
+                        warned = true;
+                    }
+
+                    time::sleep(PERMISSION_RETRY_INTERVAL).await;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        let mut read_dir = fs::read_dir(&options.path).await?;
 
         let mut inotify = Inotify::init()?;
-        inotify.add_watch(EVENT_PATH, WatchMask::CREATE)?;
+        // Device nodes are usually created before udev sets their permissions, which is reported as ATTRIB.
+        inotify.add_watch(
+            &options.path,
+            WatchMask::CREATE | WatchMask::DELETE | WatchMask::ATTRIB,
+        )?;
 
         // This buffer size should be OK, since we don't expect a lot of devices
         // to be plugged in frequently.
         let mut stream = inotify.event_stream([0; 512])?;
 
+        // Only removals of devices that were handed out are reported.
+        let mut added = HashSet::new();
+        let mut parked = Parked::default();
+        // Devices grabbed by another process, tried again all at once when it's time to.
+        let mut busy = HashSet::new();
+        let mut retry_at = None;
+        let mut retries = VecDeque::new();
+
         loop {
-            let path = match read_dir.next_entry().await? {
-                Some(entry) => entry.path(),
-                None => match stream.next().await {
-                    Some(event) => {
+            let next = match retries.pop_front() {
+                Some(path) => Some(path),
+                None => read_dir.next_entry().await?.map(|entry| entry.path()),
+            };
+
+            let (path, removed) = match next {
+                Some(path) => (path, false),
+                None => match next_event(&mut stream, parked.deadline(), retry_at).await {
+                    Next::Expired => {
+                        for path in parked.expire(time::Instant::now()) {
+                            update(&|stats| stats.failed += 1);
+                            tracing::error!(
+                                "Gave up on device {:?}, never got permission to open it",
+                                path
+                            );
+                        }
+
+                        continue;
+                    }
+                    Next::Retry => {
+                        retries.extend(busy.iter().cloned());
+                        retry_at = None;
+
+                        continue;
+                    }
+                    Next::Event(event) => {
                         let event = event?;
+
+                        // The watch is gone along with the file system, so are all the devices.
+                        if event.mask.contains(EventMask::UNMOUNT) {
+                            for path in added.drain() {
+                                if sender.send(Ok(MonitorEvent::Removed(path))).await.is_err() {
+                                    return Ok(());
+                                }
+                            }
+
+                            return Err(Error::new(
+                                ErrorKind::NotFound,
+                                "Event directory unmounted",
+                            ));
+                        }
+
                         let name = match event.name {
                             Some(name) => name,
                             None => continue,
                         };
 
-                        Path::new(EVENT_PATH).join(&name)
+                        let path = options.path.join(&name);
+
+                        if !parked.wants(event.mask, &path) {
+                            continue;
+                        }
+
+                        let removed = event.mask.contains(EventMask::DELETE);
+                        (path, removed)
                     }
-                    None => break,
+                    Next::End => break,
                 },
             };
 
@@ -73,16 +381,109 @@ async fn monitor(sender: Sender<Result<Interceptor, Error>>, input_device_paths:
                 continue;
             }
 
-            if register_input_device(&input_device_paths, path.clone()) {
-                let interceptor = match Interceptor::open(&path, &registry).await {
-                    Ok(interceptor) => interceptor,
-                    Err(OpenError::Io(err)) => return Err(err),
-                    Err(OpenError::NotAppliable) => continue,
-                };
+            // Either about to be opened again or gone.
+            let parked_until = parked.take(&path);
+            let was_busy = busy.remove(&path);
 
-                if sender.send(Ok(interceptor)).await.is_err() {
+            if removed {
+                if added.remove(&path)
+                    && sender.send(Ok(MonitorEvent::Removed(path))).await.is_err()
+                {
                     return Ok(());
                 }
+
+                continue;
+            }
+
+            if parked_until.is_none() && !was_busy {
+                update(&|stats| stats.seen += 1);
+            }
+
+            let grab_mode = options
+                .grab_modes
+                .get(&path)
+                .copied()
+                .unwrap_or(options.grab_mode);
+
+            let result = if register_input_device(&options.input_device_paths, path.clone()) {
+                let result = Interceptor::open(&path, &registry, grab_mode, |device| {
+                    options.accepts(device)
+                })
+                .await;
+
+                if let Err(err @ OpenError::Grabbed { .. }) = &result {
+                    match options.grabbed {
+                        GrabbedPolicy::Retry(interval) => {
+                            if was_busy {
+                                tracing::debug!("Device {:?}: {}", path, err);
+                            } else {
+                                tracing::warn!(
+                                    "Device {:?}: {}, retrying every {:?}",
+                                    path,
+                                    err,
+                                    interval
+                                );
+                            }
+
+                            busy.insert(path);
+                            retry_at.get_or_insert_with(|| time::Instant::now() + interval);
+                            continue;
+                        }
+                        GrabbedPolicy::Fail => {
+                            update(&|stats| stats.failed += 1);
+                            return Err(Error::new(
+                                ErrorKind::Other,
+                                format!("Device {:?}: {}", path, err),
+                            ));
+                        }
+                        GrabbedPolicy::Skip => {}
+                    }
+                }
+
+                result.map_err(|err| match err {
+                    OpenError::NotAppliable(reason) => Ok(reason.into()),
+                    OpenError::Rejected => Ok(SkipReason::Rejected),
+                    OpenError::Grabbed { .. } => Ok(SkipReason::Grabbed),
+                    OpenError::Io(err) => Err(err),
+                })
+            } else {
+                Err(Ok(SkipReason::Filtered))
+            };
+
+            let interceptor = match result {
+                Ok(interceptor) => interceptor,
+                Err(Ok(reason)) => {
+                    update(&|stats| *stats.skipped.entry(reason).or_default() += 1);
+                    skip_log.log(&path, reason);
+                    continue;
+                }
+                // The device was removed right after being created, its removal is still pending in the stream.
+                Err(Err(err))
+                    if err.kind() == ErrorKind::NotFound
+                        || err.raw_os_error() == Some(libc::ENODEV) =>
+                {
+                    tracing::debug!("Device {:?} disappeared before it could be opened", path);
+                    continue;
+                }
+                Err(Err(err)) if err.kind() == ErrorKind::PermissionDenied => {
+                    tracing::debug!(
+                        "Permission denied opening device {:?}, waiting for it to change",
+                        path
+                    );
+                    parked.park(path, parked_until, time::Instant::now());
+                    continue;
+                }
+                Err(Err(err)) => {
+                    update(&|stats| stats.failed += 1);
+                    return Err(err);
+                }
+            };
+
+            update(&|stats| stats.opened += 1);
+            added.insert(path);
+
+            if sender.send(Ok(MonitorEvent::Added(interceptor))).await.is_err() {
+                return Ok(());
             }
         }
 
@@ -100,6 +501,147 @@ async fn monitor(sender: Sender<Result<Interceptor, Error>>, input_device_paths:
     }
 }
 
+enum Next<T> {
+    Event(T),
+    // The inotify stream ended.
+    End,
+    // The earliest parked device timed out.
+    Expired,
+    // Time to try grabbing the busy devices again.
+    Retry,
+}
+
+async fn next_event<T: futures::Stream + Unpin>(
+    stream: &mut T,
+    deadline: Option<time::Instant>,
+    retry_at: Option<time::Instant>,
+) -> Next<T::Item> {
+    let sleep = |instant: Option<time::Instant>| async move {
+        match instant {
+            Some(instant) => time::sleep_until(instant).await,
+            None => future::pending().await,
+        }
+    };
+
+    tokio::select! {
+        event = stream.next() => match event {
+            Some(event) => Next::Event(event),
+            None => Next::End,
+        },
+        _ = sleep(deadline) => Next::Expired,
+        _ = sleep(retry_at) => Next::Retry,
+    }
+}
+
+// Devices that couldn't be opened for lack of permissions, with when to give up on them.
+#[derive(Default)]
+struct Parked {
+    deadlines: HashMap<PathBuf, time::Instant>,
+}
+
+impl Parked {
+    fn insert(&mut self, path: PathBuf, deadline: time::Instant) {
+        self.deadlines.insert(path, deadline);
+    }
+
+    // Parked again keeps the original deadline, permissions changing without ever allowing access don't keep the
+    // device around forever.
+    fn park(&mut self, path: PathBuf, previous: Option<time::Instant>, now: time::Instant) {
+        self.insert(path, previous.unwrap_or(now + ATTRIB_TIMEOUT));
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        self.deadlines.contains_key(path)
+    }
+
+    // Attribute changes are only interesting if the device is waiting for them.
+    fn wants(&self, mask: EventMask, path: &Path) -> bool {
+        !mask.contains(EventMask::ATTRIB) || self.contains(path)
+    }
+
+    // Returns the deadline of the device if it was parked.
+    fn take(&mut self, path: &Path) -> Option<time::Instant> {
+        self.deadlines.remove(path)
+    }
+
+    fn deadline(&self) -> Option<time::Instant> {
+        self.deadlines.values().min().copied()
+    }
+
+    // Removes and returns the devices whose deadline has passed.
+    fn expire(&mut self, now: time::Instant) -> Vec<PathBuf> {
+        let expired = self
+            .deadlines
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+
+        for path in &expired {
+            self.deadlines.remove(path);
+        }
+
+        expired
+    }
+}
+
+async fn preflight(path: &Path) -> Result<(), MonitorInitError> {
+    let map_err = |err: Error| match err.kind() {
+        ErrorKind::NotFound => MonitorInitError::Missing,
+        ErrorKind::PermissionDenied => MonitorInitError::PermissionDenied,
+        _ => MonitorInitError::Io(err),
+    };
+
+    let mut read_dir = fs::read_dir(path).await.map_err(map_err)?;
+
+    // Trying a single device is enough, they all share the same permissions.
+    while let Some(entry) = read_dir.next_entry().await? {
+        if !entry.file_name().to_string_lossy().starts_with("event") {
+            continue;
+        }
+
+        return match OpenOptions::new().read(true).write(true).open(entry.path()).await {
+            Ok(_) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::PermissionDenied => Err(MonitorInitError::PermissionDenied),
+            // The device might have been unplugged in the meantime.
+            Err(_) => continue,
+        };
+    }
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct SkipLog {
+    // The time of the last message and the number of skips since then, per reason.
+    entries: HashMap<SkipReason, (Instant, usize)>,
+}
+
+impl SkipLog {
+    fn log(&mut self, path: &Path, reason: SkipReason) {
+        let now = Instant::now();
+
+        if let Some((last, suppressed)) = self.entries.get_mut(&reason) {
+            if now.duration_since(*last) < SKIP_LOG_INTERVAL {
+                *suppressed += 1;
+                return;
+            }
+        }
+
+        let suppressed = self
+            .entries
+            .insert(reason, (now, 0))
+            .map_or(0, |(_, suppressed)| suppressed);
+
+        tracing::info!(
+            path = ?path,
+            reason = %reason,
+            suppressed = %suppressed,
+            "Skipped device"
+        );
+    }
+}
+
 fn canonicalize_input_device_paths(input_device_paths: &HashSet<String>) -> HashSet<String> {
     let mut absolute_paths = HashSet::new();
     for path in input_device_paths {
@@ -137,3 +679,56 @@ fn register_input_device(input_device_paths: &HashSet<String>, input_device_path
         return true;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parked() {
+        let now = time::Instant::now();
+        let mut parked = Parked::default();
+        assert_eq!(parked.deadline(), None);
+
+        parked.insert("/dev/input/event1".into(), now + Duration::from_secs(1));
+        parked.insert("/dev/input/event2".into(), now + Duration::from_secs(2));
+        assert_eq!(parked.deadline(), Some(now + Duration::from_secs(1)));
+
+        assert_eq!(parked.expire(now), Vec::<PathBuf>::new());
+        assert_eq!(
+            parked.expire(now + Duration::from_secs(1)),
+            [PathBuf::from("/dev/input/event1")]
+        );
+        assert!(!parked.contains(Path::new("/dev/input/event1")));
+
+        assert_eq!(
+            parked.take(Path::new("/dev/input/event2")),
+            Some(now + Duration::from_secs(2))
+        );
+        assert_eq!(parked.deadline(), None);
+    }
+
+    #[test]
+    fn park_then_retry() {
+        let now = time::Instant::now();
+        let path = Path::new("/dev/input/event1");
+        let mut parked = Parked::default();
+
+        // Created with permissions udev hasn't relaxed yet.
+        assert!(parked.wants(EventMask::CREATE, path));
+        assert!(!parked.wants(EventMask::ATTRIB, path));
+        parked.park(path.into(), None, now);
+
+        // The permissions change, the device is opened again.
+        assert!(parked.wants(EventMask::ATTRIB, path));
+        assert!(!parked.wants(EventMask::ATTRIB, Path::new("/dev/input/event2")));
+        let previous = parked.take(path);
+        assert_eq!(previous, Some(now + ATTRIB_TIMEOUT));
+
+        // Still denied, the original deadline stays.
+        parked.park(path.into(), previous, now + Duration::from_secs(1));
+        assert_eq!(parked.deadline(), Some(now + ATTRIB_TIMEOUT));
+        assert_eq!(parked.expire(now + ATTRIB_TIMEOUT), [path.to_path_buf()]);
+        assert!(!parked.wants(EventMask::ATTRIB, path));
+    }
+}
@@ -0,0 +1,51 @@
+use crate::convert::Convert;
+use crate::glue;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MscEvent {
+    pub code: Msc,
+    pub value: i32,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Msc {
+    Serial,
+    PulseLed,
+    Gesture,
+    Raw,
+    Scan,
+    Timestamp,
+}
+
+impl Convert for Msc {
+    type Raw = u16;
+
+    fn from_raw(raw: u16) -> Option<Self> {
+        let msc = match raw as _ {
+            glue::MSC_SERIAL => Self::Serial,
+            glue::MSC_PULSELED => Self::PulseLed,
+            glue::MSC_GESTURE => Self::Gesture,
+            glue::MSC_RAW => Self::Raw,
+            glue::MSC_SCAN => Self::Scan,
+            glue::MSC_TIMESTAMP => Self::Timestamp,
+            _ => return None,
+        };
+
+        Some(msc)
+    }
+
+    fn to_raw(&self) -> Option<u16> {
+        let raw = match self {
+            Self::Serial => glue::MSC_SERIAL,
+            Self::PulseLed => glue::MSC_PULSELED,
+            Self::Gesture => glue::MSC_GESTURE,
+            Self::Raw => glue::MSC_RAW,
+            Self::Scan => glue::MSC_SCAN,
+            Self::Timestamp => glue::MSC_TIMESTAMP,
+        };
+
+        Some(raw as _)
+    }
+}
@@ -0,0 +1,265 @@
+use crate::abs::{AbsAxis, AbsInfo};
+use crate::glue;
+use crate::key::{Button, Key, Keyboard};
+use crate::rel::RelAxis;
+
+use std::ffi::CStr;
+
+// Well known devices which can be impersonated by a writer, matching the IDs and capabilities
+// the kernel drivers report for the real hardware. Some software only accepts specific devices.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DeviceProfile {
+    // A wired controller as reported by the xpad driver.
+    Xbox360Controller,
+    // A standard PC keyboard as reported by the atkbd driver.
+    Generic104KeyKeyboard,
+    // A basic PS/2 mouse as reported by the psmouse driver.
+    TwoButtonMouse,
+}
+
+impl DeviceProfile {
+    pub const ALL: [Self; 3] = [
+        Self::Xbox360Controller,
+        Self::Generic104KeyKeyboard,
+        Self::TwoButtonMouse,
+    ];
+
+    // Case insensitive, spaces, dashes and underscores are treated the same.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let normalize = |name: &str| {
+            name.chars()
+                .map(|c| match c {
+                    ' ' | '_' => '-',
+                    c => c.to_ascii_lowercase(),
+                })
+                .collect::<String>()
+        };
+
+        let name = normalize(name);
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|profile| normalize(profile.name()) == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Xbox360Controller => "Xbox 360 Controller",
+            Self::Generic104KeyKeyboard => "Generic 104-key Keyboard",
+            Self::TwoButtonMouse => "2-button Mouse",
+        }
+    }
+
+    // The name of the real device.
+    pub fn device_name(&self) -> &'static CStr {
+        let name: &[u8] = match self {
+            Self::Xbox360Controller => b"Microsoft X-Box 360 pad\0",
+            Self::Generic104KeyKeyboard => b"AT Translated Set 2 keyboard\0",
+            Self::TwoButtonMouse => b"PS/2 Generic Mouse\0",
+        };
+
+        CStr::from_bytes_with_nul(name).unwrap()
+    }
+
+    pub fn bus_type(&self) -> u16 {
+        let bus = match self {
+            Self::Xbox360Controller => glue::BUS_USB,
+            Self::Generic104KeyKeyboard | Self::TwoButtonMouse => glue::BUS_I8042,
+        };
+
+        bus as _
+    }
+
+    pub fn vendor(&self) -> u16 {
+        match self {
+            Self::Xbox360Controller => 0x045e,
+            Self::Generic104KeyKeyboard => 0x0001,
+            Self::TwoButtonMouse => 0x0002,
+        }
+    }
+
+    pub fn product(&self) -> u16 {
+        match self {
+            Self::Xbox360Controller => 0x028e,
+            Self::Generic104KeyKeyboard | Self::TwoButtonMouse => 0x0001,
+        }
+    }
+
+    pub fn version(&self) -> u16 {
+        match self {
+            Self::Xbox360Controller => 0x0114,
+            Self::Generic104KeyKeyboard => 0xab41,
+            Self::TwoButtonMouse => 0x0000,
+        }
+    }
+
+    pub fn rel(&self) -> Vec<RelAxis> {
+        match self {
+            Self::TwoButtonMouse => vec![RelAxis::X, RelAxis::Y],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn abs(&self) -> Vec<(AbsAxis, AbsInfo)> {
+        let info = |min, max, fuzz, flat| AbsInfo {
+            min,
+            max,
+            fuzz,
+            flat,
+            resolution: 0,
+        };
+
+        match self {
+            Self::Xbox360Controller => vec![
+                (AbsAxis::X, info(-32768, 32767, 16, 128)),
+                (AbsAxis::Y, info(-32768, 32767, 16, 128)),
+                (AbsAxis::Rx, info(-32768, 32767, 16, 128)),
+                (AbsAxis::Ry, info(-32768, 32767, 16, 128)),
+                (AbsAxis::Z, info(0, 255, 0, 0)),
+                (AbsAxis::Rz, info(0, 255, 0, 0)),
+                (AbsAxis::Hat0X, info(-1, 1, 0, 0)),
+                (AbsAxis::Hat0Y, info(-1, 1, 0, 0)),
+            ],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn keys(&self) -> Vec<Key> {
+        match self {
+            Self::Xbox360Controller => [
+                Button::South,
+                Button::East,
+                Button::North,
+                Button::West,
+                Button::TL,
+                Button::Tr,
+                Button::Select,
+                Button::Start,
+                Button::Mode,
+                Button::ThumbL,
+                Button::ThumbR,
+            ]
+            .iter()
+            .copied()
+            .map(Key::Button)
+            .collect(),
+            Self::Generic104KeyKeyboard => KEYBOARD_104.iter().copied().map(Key::Key).collect(),
+            Self::TwoButtonMouse => vec![Key::Button(Button::Left), Key::Button(Button::Right)],
+        }
+    }
+}
+
+const KEYBOARD_104: [Keyboard; 104] = [
+    // Function row.
+    Keyboard::Esc,
+    Keyboard::F1,
+    Keyboard::F2,
+    Keyboard::F3,
+    Keyboard::F4,
+    Keyboard::F5,
+    Keyboard::F6,
+    Keyboard::F7,
+    Keyboard::F8,
+    Keyboard::F9,
+    Keyboard::F10,
+    Keyboard::F11,
+    Keyboard::F12,
+    Keyboard::SysRq,
+    Keyboard::ScrollLock,
+    Keyboard::Pause,
+    // Number row.
+    Keyboard::Grave,
+    Keyboard::N1,
+    Keyboard::N2,
+    Keyboard::N3,
+    Keyboard::N4,
+    Keyboard::N5,
+    Keyboard::N6,
+    Keyboard::N7,
+    Keyboard::N8,
+    Keyboard::N9,
+    Keyboard::N0,
+    Keyboard::Minus,
+    Keyboard::Equal,
+    Keyboard::Backspace,
+    // Top row.
+    Keyboard::Tab,
+    Keyboard::Q,
+    Keyboard::W,
+    Keyboard::E,
+    Keyboard::R,
+    Keyboard::T,
+    Keyboard::Y,
+    Keyboard::U,
+    Keyboard::I,
+    Keyboard::O,
+    Keyboard::P,
+    Keyboard::LeftBrace,
+    Keyboard::RightBrace,
+    Keyboard::Backslash,
+    // Home row.
+    Keyboard::CapsLock,
+    Keyboard::A,
+    Keyboard::S,
+    Keyboard::D,
+    Keyboard::F,
+    Keyboard::G,
+    Keyboard::H,
+    Keyboard::J,
+    Keyboard::K,
+    Keyboard::L,
+    Keyboard::Semicolon,
+    Keyboard::Apostrophe,
+    Keyboard::Enter,
+    // Bottom row.
+    Keyboard::LeftShift,
+    Keyboard::Z,
+    Keyboard::X,
+    Keyboard::C,
+    Keyboard::V,
+    Keyboard::B,
+    Keyboard::N,
+    Keyboard::M,
+    Keyboard::Comma,
+    Keyboard::Dot,
+    Keyboard::Slash,
+    Keyboard::RightShift,
+    // Modifier row.
+    Keyboard::LeftCtrl,
+    Keyboard::LeftMeta,
+    Keyboard::LeftAlt,
+    Keyboard::Space,
+    Keyboard::RightAlt,
+    Keyboard::RightMeta,
+    Keyboard::Compose,
+    Keyboard::RightCtrl,
+    // Navigation block.
+    Keyboard::Insert,
+    Keyboard::Home,
+    Keyboard::PageUp,
+    Keyboard::Delete,
+    Keyboard::End,
+    Keyboard::PageDown,
+    Keyboard::Up,
+    Keyboard::Left,
+    Keyboard::Down,
+    Keyboard::Right,
+    // Numpad.
+    Keyboard::NumLock,
+    Keyboard::KpSlash,
+    Keyboard::KpAsterisk,
+    Keyboard::KpMinus,
+    Keyboard::Kp7,
+    Keyboard::Kp8,
+    Keyboard::Kp9,
+    Keyboard::KpPlus,
+    Keyboard::Kp4,
+    Keyboard::Kp5,
+    Keyboard::Kp6,
+    Keyboard::Kp1,
+    Keyboard::Kp2,
+    Keyboard::Kp3,
+    Keyboard::KpEnter,
+    Keyboard::Kp0,
+    Keyboard::KpDot,
+];
@@ -0,0 +1,49 @@
+use crate::convert::Convert;
+use crate::glue;
+
+use serde::{Deserialize, Serialize};
+
+// Device properties, which tell userspace how to interpret the events of the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum InputProp {
+    Pointer,
+    Direct,
+    ButtonPad,
+    SemiMt,
+    TopButtonPad,
+    PointingStick,
+    Accelerometer,
+}
+
+impl Convert for InputProp {
+    type Raw = u16;
+
+    fn from_raw(raw: Self::Raw) -> Option<Self> {
+        let prop = match raw as _ {
+            glue::INPUT_PROP_POINTER => Self::Pointer,
+            glue::INPUT_PROP_DIRECT => Self::Direct,
+            glue::INPUT_PROP_BUTTONPAD => Self::ButtonPad,
+            glue::INPUT_PROP_SEMI_MT => Self::SemiMt,
+            glue::INPUT_PROP_TOPBUTTONPAD => Self::TopButtonPad,
+            glue::INPUT_PROP_POINTING_STICK => Self::PointingStick,
+            glue::INPUT_PROP_ACCELEROMETER => Self::Accelerometer,
+            _ => return None,
+        };
+
+        Some(prop)
+    }
+
+    fn to_raw(&self) -> Option<Self::Raw> {
+        let raw = match self {
+            Self::Pointer => glue::INPUT_PROP_POINTER,
+            Self::Direct => glue::INPUT_PROP_DIRECT,
+            Self::ButtonPad => glue::INPUT_PROP_BUTTONPAD,
+            Self::SemiMt => glue::INPUT_PROP_SEMI_MT,
+            Self::TopButtonPad => glue::INPUT_PROP_TOPBUTTONPAD,
+            Self::PointingStick => glue::INPUT_PROP_POINTING_STICK,
+            Self::Accelerometer => glue::INPUT_PROP_ACCELEROMETER,
+        };
+
+        Some(raw as _)
+    }
+}
@@ -3,7 +3,7 @@ use crate::glue;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RelEvent {
     pub axis: RelAxis,
     pub value: i32,
@@ -0,0 +1,40 @@
+use crate::convert::Convert;
+use crate::glue;
+
+use serde::{Deserialize, Serialize};
+
+// Changes the auto repeat settings, in milliseconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepEvent {
+    pub code: Rep,
+    pub value: i32,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Rep {
+    Delay,
+    Period,
+}
+
+impl Convert for Rep {
+    type Raw = u16;
+
+    fn from_raw(raw: Self::Raw) -> Option<Self> {
+        let rep = match raw as _ {
+            glue::REP_DELAY => Self::Delay,
+            glue::REP_PERIOD => Self::Period,
+            _ => return None,
+        };
+
+        Some(rep)
+    }
+
+    fn to_raw(&self) -> Option<Self::Raw> {
+        let raw = match self {
+            Self::Delay => glue::REP_DELAY,
+            Self::Period => glue::REP_PERIOD,
+        };
+
+        Some(raw as _)
+    }
+}
@@ -0,0 +1,228 @@
+use crate::abs::AbsAxis;
+use crate::convert::Convert;
+use crate::glue;
+use crate::key::Key;
+use crate::rel::RelAxis;
+
+use std::fmt::{self, Debug, Formatter};
+use std::hash::{Hash, Hasher};
+use std::iter::{self, FromIterator};
+use std::marker::PhantomData;
+
+// Keys have by far the most codes, so this is enough for every event type.
+const WORDS: usize = (glue::KEY_CNT as usize).div_ceil(64);
+
+pub type KeySet = CodeSet<Key>;
+pub type RelSet = CodeSet<RelAxis>;
+pub type AbsSet = CodeSet<AbsAxis>;
+
+// A fixed size bit set of event codes, which makes membership tests and set operations allocation free.
+pub struct CodeSet<T> {
+    bits: [u64; WORDS],
+    _marker: PhantomData<T>,
+}
+
+impl<T: Convert<Raw = u16>> CodeSet<T> {
+    pub fn new() -> Self {
+        Self::from_bits([0; WORDS])
+    }
+
+    // Returns whether the value was newly inserted.
+    pub fn insert(&mut self, value: T) -> bool {
+        let (word, mask) = match value.to_raw().and_then(locate) {
+            Some(location) => location,
+            None => return false,
+        };
+
+        let inserted = self.bits[word] & mask == 0;
+        self.bits[word] |= mask;
+
+        inserted
+    }
+
+    // Returns whether the value was present.
+    pub fn remove(&mut self, value: &T) -> bool {
+        let (word, mask) = match value.to_raw().and_then(locate) {
+            Some(location) => location,
+            None => return false,
+        };
+
+        let removed = self.bits[word] & mask != 0;
+        self.bits[word] &= !mask;
+
+        removed
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        match value.to_raw().and_then(locate) {
+            Some((word, mask)) => self.bits[word] & mask != 0,
+            None => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bits
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|word| *word == 0)
+    }
+
+    pub fn clear(&mut self) {
+        self.bits = [0; WORDS];
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        self.combine(other, |a, b| a | b)
+    }
+
+    pub fn intersection(&self, other: &Self) -> Self {
+        self.combine(other, |a, b| a & b)
+    }
+
+    pub fn difference(&self, other: &Self) -> Self {
+        self.combine(other, |a, b| a & !b)
+    }
+
+    pub fn is_subset(&self, other: &Self) -> bool {
+        self.difference(other).is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        self.bits
+            .iter()
+            .enumerate()
+            .flat_map(|(i, word)| {
+                let mut word = *word;
+
+                iter::from_fn(move || {
+                    if word == 0 {
+                        return None;
+                    }
+
+                    let bit = word.trailing_zeros();
+                    word &= word - 1;
+
+                    Some((i * 64) as u16 + bit as u16)
+                })
+            })
+            .filter_map(T::from_raw)
+    }
+
+    fn from_bits(bits: [u64; WORDS]) -> Self {
+        Self {
+            bits,
+            _marker: PhantomData,
+        }
+    }
+
+    fn combine(&self, other: &Self, f: impl Fn(u64, u64) -> u64) -> Self {
+        let mut bits = [0; WORDS];
+        for (i, word) in bits.iter_mut().enumerate() {
+            *word = f(self.bits[i], other.bits[i]);
+        }
+
+        Self::from_bits(bits)
+    }
+}
+
+fn locate(code: u16) -> Option<(usize, u64)> {
+    let word = code as usize / 64;
+    if word >= WORDS {
+        return None;
+    }
+
+    Some((word, 1 << (code % 64)))
+}
+
+impl<T: Convert<Raw = u16>> Default for CodeSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Clone for CodeSet<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for CodeSet<T> {}
+
+impl<T> PartialEq for CodeSet<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bits == other.bits
+    }
+}
+
+impl<T> Eq for CodeSet<T> {}
+
+impl<T> Hash for CodeSet<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.bits.hash(state);
+    }
+}
+
+impl<T: Convert<Raw = u16> + Debug> Debug for CodeSet<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl<T: Convert<Raw = u16>> FromIterator<T> for CodeSet<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut set = Self::new();
+        set.extend(iter);
+
+        set
+    }
+}
+
+impl<T: Convert<Raw = u16>> Extend<T> for CodeSet<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for value in iter {
+            self.insert(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::key::{Button, Keyboard};
+
+    #[test]
+    fn set_operations() {
+        let a = [
+            Key::Key(Keyboard::A),
+            Key::Key(Keyboard::B),
+            Key::Button(Button::Left),
+        ]
+        .iter()
+        .copied()
+        .collect::<KeySet>();
+
+        let b = [Key::Key(Keyboard::B), Key::Button(Button::Right)]
+            .iter()
+            .copied()
+            .collect::<KeySet>();
+
+        assert_eq!(a.len(), 3);
+        assert!(a.contains(&Key::Button(Button::Left)));
+        assert!(!a.contains(&Key::Button(Button::Right)));
+
+        assert_eq!(a.union(&b).len(), 4);
+        assert_eq!(
+            a.intersection(&b).iter().collect::<Vec<_>>(),
+            [Key::Key(Keyboard::B)]
+        );
+        assert_eq!(
+            a.difference(&b).iter().collect::<Vec<_>>(),
+            [Key::Key(Keyboard::A), Key::Button(Button::Left)]
+        );
+        assert!(a.intersection(&b).is_subset(&b));
+    }
+}
@@ -0,0 +1,93 @@
+use crate::abs::{AbsAxis, AbsEvent, ToolType};
+use crate::convert::Convert;
+use crate::evdev::Evdev;
+use crate::glue;
+use crate::key::Key;
+use crate::set::KeySet;
+use crate::switch::Switch;
+
+use std::collections::HashMap;
+
+// Everything about a device that persists between events, as opposed to relative motion.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceState {
+    pub keys: KeySet,
+    pub abs: HashMap<AbsAxis, i32>,
+    // Multitouch axes, indexed by slot.
+    pub slots: Vec<Vec<AbsEvent>>,
+    pub current_slot: Option<i32>,
+    pub switches: HashMap<Switch, bool>,
+    // LEDs use raw codes, so that those without a typed counterpart are kept as well.
+    pub leds: HashMap<u16, bool>,
+}
+
+impl DeviceState {
+    pub(crate) fn from_evdev(evdev: &Evdev) -> Self {
+        let has = |r#type, code| unsafe {
+            glue::libevdev_has_event_code(evdev.as_ptr(), r#type, code) == 1
+        };
+
+        let value =
+            |r#type, code| unsafe { glue::libevdev_get_event_value(evdev.as_ptr(), r#type, code) };
+
+        let keys = (0..glue::KEY_CNT)
+            .filter(|code| has(glue::EV_KEY, *code) && value(glue::EV_KEY, *code) != 0)
+            .filter_map(|code| Key::from_raw(code as _))
+            .collect();
+
+        let abs = (0..glue::ABS_CNT)
+            .filter(|code| !is_mt(*code) && has(glue::EV_ABS, *code))
+            .filter_map(|code| Some((AbsAxis::from_raw(code as _)?, value(glue::EV_ABS, code))))
+            .collect();
+
+        let switches = (0..glue::SW_CNT)
+            .filter(|code| has(glue::EV_SW, *code))
+            .filter_map(|code| Some((Switch::from_raw(code as _)?, value(glue::EV_SW, code) != 0)))
+            .collect();
+
+        let count = unsafe { glue::libevdev_get_num_slots(evdev.as_ptr()) };
+        let slots = (0..count.max(0))
+            .map(|slot| {
+                (glue::ABS_MT_SLOT + 1..glue::ABS_CNT)
+                    .filter(|code| is_mt(*code) && has(glue::EV_ABS, *code))
+                    .filter_map(|code| {
+                        let value = unsafe {
+                            glue::libevdev_get_slot_value(evdev.as_ptr(), slot as _, code)
+                        };
+
+                        match code {
+                            glue::ABS_MT_TOOL_TYPE => ToolType::from_raw(value)
+                                .map(|value| AbsEvent::MtToolType { value }),
+                            _ => AbsAxis::from_raw(code as _)
+                                .map(|axis| AbsEvent::Axis { axis, value }),
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let current_slot = (count > 0)
+            .then(|| unsafe { glue::libevdev_get_current_slot(evdev.as_ptr()) })
+            .filter(|slot| *slot >= 0);
+
+        let raw = |r#type, count| {
+            (0..count)
+                .filter(|code| has(r#type, *code))
+                .map(|code| (code as u16, value(r#type, code) != 0))
+                .collect()
+        };
+
+        Self {
+            keys,
+            abs,
+            slots,
+            current_slot,
+            switches,
+            leds: raw(glue::EV_LED, glue::LED_CNT),
+        }
+    }
+}
+
+fn is_mt(code: u32) -> bool {
+    (glue::ABS_MT_SLOT..=glue::ABS_MT_TOOL_Y).contains(&code)
+}
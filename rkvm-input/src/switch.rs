@@ -0,0 +1,86 @@
+use crate::convert::Convert;
+use crate::glue;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub struct SwitchEvent {
+    pub switch: Switch,
+    pub value: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub enum Switch {
+    Lid,
+    TabletMode,
+    HeadphoneInsert,
+    RfkillAll,
+    MicrophoneInsert,
+    Dock,
+    LineoutInsert,
+    JackPhysicalInsert,
+    VideooutInsert,
+    CameraLensCover,
+    KeypadSlide,
+    FrontProximity,
+    RotateLock,
+    LineinInsert,
+    MuteDevice,
+    PenInserted,
+    // Any other code below SW_CNT, for switches added to the kernel since.
+    Other(u16),
+}
+
+impl Convert for Switch {
+    type Raw = u16;
+
+    fn from_raw(raw: Self::Raw) -> Option<Self> {
+        let switch = match raw as _ {
+            glue::SW_LID => Self::Lid,
+            glue::SW_TABLET_MODE => Self::TabletMode,
+            glue::SW_HEADPHONE_INSERT => Self::HeadphoneInsert,
+            glue::SW_RFKILL_ALL => Self::RfkillAll,
+            glue::SW_MICROPHONE_INSERT => Self::MicrophoneInsert,
+            glue::SW_DOCK => Self::Dock,
+            glue::SW_LINEOUT_INSERT => Self::LineoutInsert,
+            glue::SW_JACK_PHYSICAL_INSERT => Self::JackPhysicalInsert,
+            glue::SW_VIDEOOUT_INSERT => Self::VideooutInsert,
+            glue::SW_CAMERA_LENS_COVER => Self::CameraLensCover,
+            glue::SW_KEYPAD_SLIDE => Self::KeypadSlide,
+            glue::SW_FRONT_PROXIMITY => Self::FrontProximity,
+            glue::SW_ROTATE_LOCK => Self::RotateLock,
+            glue::SW_LINEIN_INSERT => Self::LineinInsert,
+            glue::SW_MUTE_DEVICE => Self::MuteDevice,
+            glue::SW_PEN_INSERTED => Self::PenInserted,
+            code if code < glue::SW_CNT => Self::Other(raw),
+            _ => return None,
+        };
+
+        Some(switch)
+    }
+
+    fn to_raw(&self) -> Option<Self::Raw> {
+        let raw = match self {
+            Self::Lid => glue::SW_LID,
+            Self::TabletMode => glue::SW_TABLET_MODE,
+            Self::HeadphoneInsert => glue::SW_HEADPHONE_INSERT,
+            Self::RfkillAll => glue::SW_RFKILL_ALL,
+            Self::MicrophoneInsert => glue::SW_MICROPHONE_INSERT,
+            Self::Dock => glue::SW_DOCK,
+            Self::LineoutInsert => glue::SW_LINEOUT_INSERT,
+            Self::JackPhysicalInsert => glue::SW_JACK_PHYSICAL_INSERT,
+            Self::VideooutInsert => glue::SW_VIDEOOUT_INSERT,
+            Self::CameraLensCover => glue::SW_CAMERA_LENS_COVER,
+            Self::KeypadSlide => glue::SW_KEYPAD_SLIDE,
+            Self::FrontProximity => glue::SW_FRONT_PROXIMITY,
+            Self::RotateLock => glue::SW_ROTATE_LOCK,
+            Self::LineinInsert => glue::SW_LINEIN_INSERT,
+            Self::MuteDevice => glue::SW_MUTE_DEVICE,
+            Self::PenInserted => glue::SW_PEN_INSERTED,
+            Self::Other(code) if (*code as u32) < glue::SW_CNT => *code as _,
+            Self::Other(_) => return None,
+        };
+
+        Some(raw as _)
+    }
+}
@@ -1,14 +1,44 @@
+use crate::abs::AbsAxis;
+use crate::convert::Convert;
 use crate::evdev::Evdev;
-use crate::glue::{self, libevdev_uinput};
+use crate::glue::{
+    self, ff_effect, input_event, libevdev_uinput, uinput_ff_erase, uinput_ff_upload,
+};
 
 use std::fs::File;
-use std::io::Error;
-use std::mem::MaybeUninit;
+use std::io::{Error, ErrorKind};
+use std::mem::{self, MaybeUninit};
 use std::os::fd::AsRawFd;
+use std::os::raw::{c_uint, c_ulong};
 use std::ptr::NonNull;
+use std::task::{self, Context, Poll};
 use tokio::fs::OpenOptions;
 use tokio::io::unix::AsyncFd;
 
+// _IOR(UINPUT_IOCTL_BASE, 45, unsigned int), bindgen doesn't expand the ioctl macros.
+const UI_GET_VERSION: c_ulong = (2 << 30) | (4 << 16) | ((b'U' as c_ulong) << 8) | 45;
+
+// _IOWR(UINPUT_IOCTL_BASE, 200, struct uinput_ff_upload) to _IOW(UINPUT_IOCTL_BASE, 203, struct uinput_ff_erase).
+const UI_BEGIN_FF_UPLOAD: c_ulong = (3 << 30)
+    | ((mem::size_of::<uinput_ff_upload>() as c_ulong) << 16)
+    | ((b'U' as c_ulong) << 8)
+    | 200;
+const UI_END_FF_UPLOAD: c_ulong = (1 << 30)
+    | ((mem::size_of::<uinput_ff_upload>() as c_ulong) << 16)
+    | ((b'U' as c_ulong) << 8)
+    | 201;
+const UI_BEGIN_FF_ERASE: c_ulong = (3 << 30)
+    | ((mem::size_of::<uinput_ff_erase>() as c_ulong) << 16)
+    | ((b'U' as c_ulong) << 8)
+    | 202;
+const UI_END_FF_ERASE: c_ulong = (1 << 30)
+    | ((mem::size_of::<uinput_ff_erase>() as c_ulong) << 16)
+    | ((b'U' as c_ulong) << 8)
+    | 203;
+
+// The first version with UI_DEV_SETUP and UI_ABS_SETUP, which libevdev uses when available.
+const ABS_SETUP_VERSION: c_uint = 5;
+
 pub struct Uinput {
     file: AsyncFd<File>,
     uinput: NonNull<libevdev_uinput>,
@@ -27,6 +57,10 @@ impl Uinput {
 
         let file = AsyncFd::new(file)?;
 
+        if !matches!(version(&file), Some(version) if version >= ABS_SETUP_VERSION) {
+            warn_legacy(evdev);
+        }
+
         let mut uinput = MaybeUninit::uninit();
 
         let ret = unsafe {
@@ -54,6 +88,120 @@ impl Uinput {
     pub fn as_ptr(&self) -> *mut libevdev_uinput {
         self.uinput.as_ptr()
     }
+
+    // Events the kernel sends to the device, such as LED changes requested by userspace.
+    pub fn poll_read(&self, cx: &mut Context<'_>) -> Poll<Result<input_event, Error>> {
+        loop {
+            let mut guard = task::ready!(self.file.poll_read_ready(cx))?;
+
+            let result = guard.try_io(|file| {
+                let mut event = MaybeUninit::<input_event>::uninit();
+                let size = mem::size_of::<input_event>();
+
+                let ret = unsafe { libc::read(file.as_raw_fd(), event.as_mut_ptr() as _, size) };
+                if ret < 0 {
+                    return Err(Error::last_os_error());
+                }
+
+                if ret as usize != size {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "Short read from uinput",
+                    ));
+                }
+
+                Ok(unsafe { event.assume_init() })
+            });
+
+            if let Ok(result) = result {
+                return Poll::Ready(result);
+            }
+        }
+    }
+
+    // Answers the upload request read as UI_FF_UPLOAD, the process uploading the effect is blocked until then.
+    // The upload fails with EINVAL unless `accept` maps the effect to something.
+    pub fn ff_upload<T>(
+        &self,
+        request_id: u32,
+        accept: impl FnOnce(&ff_effect) -> Option<T>,
+    ) -> Result<Option<T>, Error> {
+        let mut upload: uinput_ff_upload = unsafe { mem::zeroed() };
+        upload.request_id = request_id;
+
+        let ret =
+            unsafe { libc::ioctl(self.file.as_raw_fd(), UI_BEGIN_FF_UPLOAD as _, &mut upload) };
+        if ret < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let accepted = accept(&upload.effect);
+        upload.retval = match accepted {
+            Some(_) => 0,
+            None => -libc::EINVAL,
+        };
+
+        let ret = unsafe { libc::ioctl(self.file.as_raw_fd(), UI_END_FF_UPLOAD as _, &upload) };
+        if ret < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(accepted)
+    }
+
+    // Answers the erase request read as UI_FF_ERASE, returning the ID of the effect being erased.
+    pub fn ff_erase(&self, request_id: u32) -> Result<i16, Error> {
+        let mut erase: uinput_ff_erase = unsafe { mem::zeroed() };
+        erase.request_id = request_id;
+
+        let ret = unsafe { libc::ioctl(self.file.as_raw_fd(), UI_BEGIN_FF_ERASE as _, &mut erase) };
+        if ret < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        erase.retval = 0;
+
+        let ret = unsafe { libc::ioctl(self.file.as_raw_fd(), UI_END_FF_ERASE as _, &erase) };
+        if ret < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(erase.effect_id as _)
+    }
+}
+
+// Kernels that predate UI_GET_VERSION fail it with EINVAL.
+fn version(file: &AsyncFd<File>) -> Option<c_uint> {
+    let mut version: c_uint = 0;
+
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), UI_GET_VERSION as _, &mut version) };
+    if ret < 0 {
+        return None;
+    }
+
+    Some(version)
+}
+
+// Without UI_ABS_SETUP, libevdev falls back to the legacy uinput_user_dev struct. It has room for every axis,
+// but not for their resolutions, which end up as 0.
+fn warn_legacy(evdev: &Evdev) {
+    let axes = (0..glue::ABS_CNT)
+        .filter(|code| {
+            let info = unsafe { glue::libevdev_get_abs_info(evdev.as_ptr(), *code).as_ref() };
+            matches!(info, Some(info) if info.resolution != 0)
+        })
+        .map(|code| match AbsAxis::from_raw(code as _) {
+            Some(axis) => format!("{:?}", axis),
+            None => code.to_string(),
+        })
+        .collect::<Vec<_>>();
+
+    if !axes.is_empty() {
+        tracing::warn!(
+            "uinput does not support UI_ABS_SETUP, dropping the resolution of absolute axes {}",
+            axes.join(", ")
+        );
+    }
 }
 
 impl Drop for Uinput {
@@ -1,20 +1,111 @@
-use crate::abs::{AbsAxis, AbsEvent, AbsInfo};
+mod shared;
+
+pub use shared::SharedWriter;
+
+use crate::abs::{AbsAxis, AbsInfo};
+use crate::capabilities::DeviceCapabilities;
 use crate::convert::Convert;
 use crate::evdev::Evdev;
-use crate::event::Event;
+use crate::event::{Event, EventType};
+use crate::ff::{FfEffect, FfEffectType, FfRequest};
+use crate::frame::{Frame, MAX_FRAME_EVENTS};
 use crate::glue::{self, input_absinfo};
-use crate::key::{Key, KeyEvent};
-use crate::rel::{RelAxis, RelEvent};
+use crate::interceptor::Interceptor;
+use crate::key::{Button, Key, KeyEvent};
+use crate::led::{Led, LedEvent};
+use crate::msc::Msc;
+use crate::profile::DeviceProfile;
+use crate::prop::InputProp;
+use crate::rel::RelAxis;
+use crate::state::DeviceState;
+use crate::switch::Switch;
+use crate::sync::SyncEvent;
 use crate::uinput::Uinput;
 
-use std::ffi::{CStr, OsStr};
-use std::io::Error;
+use futures::StreamExt;
+use inotify::{Inotify, WatchMask};
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CStr, CString, OsStr};
+use std::fs;
+use std::future;
+use std::io::{Error, ErrorKind};
 use std::os::unix::ffi::OsStrExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::ptr;
+use std::task::{self, Context, Poll};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::time::{self, Instant};
+
+// The longest device name uinput accepts, not counting the terminating nul byte.
+pub const MAX_NAME_LEN: usize = glue::UINPUT_MAX_NAME_SIZE as usize - 1;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // Wait until the device accepts the event.
+    #[default]
+    Block,
+    // Drop relative and absolute motion that can't be written right away, but wait for everything else.
+    // Multitouch slot changes and tracking IDs are not motion, dropping them would mix up the contacts.
+    DropMotion,
+    // Drop any event that can't be written right away.
+    DropAll,
+}
+
+impl OverflowPolicy {
+    fn drops(&self, r#type: u16, code: u16) -> bool {
+        match self {
+            Self::Block => false,
+            Self::DropMotion if r#type == glue::EV_ABS as _ => {
+                code != glue::ABS_MT_SLOT as _ && code != glue::ABS_MT_TRACKING_ID as _
+            }
+            Self::DropMotion => r#type == glue::EV_REL as _,
+            Self::DropAll => true,
+        }
+    }
+}
+
+// Requests userspace makes to the device, which only a real device can carry out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceRequest {
+    Led(LedEvent),
+    Ff(FfRequest),
+}
+
+#[derive(Error, Debug)]
+#[error("Failed to write event after writing {written}: {error}")]
+pub struct BatchError {
+    // The number of events written before the one that failed.
+    pub written: usize,
+    #[source]
+    pub error: Error,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MtProtocol {
+    // Anonymous contacts, each one terminated by SyncEvent::Mt, see `MtFrame`.
+    A,
+    // Contacts in slots, only reporting changes.
+    B,
+}
 
 pub struct Writer {
     uinput: Uinput,
+    // Copied out of libevdev when the device is created, it might not exist until udev gets to it.
+    path: Option<PathBuf>,
+    syspath: Option<PathBuf>,
+    overflow_policy: OverflowPolicy,
+    // Ranges of the absolute axes, keyed by code. Axes without a range (min and max both 0) are left out.
+    abs_ranges: HashMap<u16, (i32, i32)>,
+    abs_clamp: bool,
+    wheel_notch: Option<WheelNotch>,
+    wheel_synth: WheelSynth,
+    // The (type, code) pairs the device was created with.
+    codes: HashSet<(u16, u16)>,
+    // The sequence number of the last event applied by `write_seq`, per key.
+    key_sequences: HashMap<Key, u64>,
+    // Frames can come from the network, so `write_frame` doesn't write more than this many events of one.
+    max_frame_events: usize,
 }
 
 impl Writer {
@@ -23,80 +114,671 @@ impl Writer {
     }
 
     pub async fn write(&mut self, event: &Event) -> Result<(), Error> {
-        let (r#type, code, value) = match event {
-            Event::Rel(RelEvent { axis, value }) => (glue::EV_REL, axis.to_raw(), Some(*value)),
-            Event::Abs(event) => match event {
-                AbsEvent::Axis { axis, value } => (glue::EV_ABS, axis.to_raw(), Some(*value)),
-                AbsEvent::MtToolType { value } => (
-                    glue::EV_ABS,
-                    Some(glue::ABS_MT_TOOL_TYPE as _),
-                    value.to_raw(),
-                ),
-            },
-            Event::Key(KeyEvent { down, key }) => (glue::EV_KEY, key.to_raw(), Some(*down as _)),
-            Event::Sync(event) => (glue::EV_SYN, event.to_raw(), Some(0)),
+        if let Some((r#type, code, value)) = event.to_raw() {
+            self.write_raw(r#type, code, value).await?;
+        }
+
+        Ok(())
+    }
+
+    // Writes the events in order, stopping at the first error. Like all writes, they only wait for the device
+    // if it stops keeping up.
+    pub async fn write_batch(&mut self, events: &[Event]) -> Result<(), BatchError> {
+        for (written, event) in events.iter().enumerate() {
+            self.write(event)
+                .await
+                .map_err(|error| BatchError { written, error })?;
+        }
+
+        Ok(())
+    }
+
+    // Like `write`, but gives up with ErrorKind::TimedOut if the device doesn't accept the event in time,
+    // for example because whatever reads it stalled.
+    pub async fn write_timeout(&mut self, event: &Event, timeout: Duration) -> Result<(), Error> {
+        if let Some((r#type, code, value)) = event.to_raw() {
+            self.write_raw_timeout(r#type, code, value, timeout).await?;
+        }
+
+        Ok(())
+    }
+
+    // Writes the event unless it is a key event older than the last one applied for the same key,
+    // which guards against reordering on transports that don't preserve order. Returns whether it was written.
+    // Only events written through this are compared, restoring a state or resyncing doesn't affect the sequence
+    // numbers, so a stale event arriving after either of those is still ignored.
+    pub async fn write_seq(&mut self, sequence: u64, event: &Event) -> Result<bool, Error> {
+        if let Event::Key(KeyEvent { key, .. }) = event {
+            match self.key_sequences.get(key) {
+                Some(last) if *last >= sequence => {
+                    tracing::trace!(
+                        "Ignored stale event {:?} with sequence number {}, already applied {}",
+                        event,
+                        sequence,
+                        last
+                    );
+
+                    return Ok(false);
+                }
+                _ => {
+                    self.key_sequences.insert(*key, sequence);
+                }
+            }
+        }
+
+        self.write(event).await?;
+
+        Ok(true)
+    }
+
+    // Writes the whole frame, terminating it with SyncEvent::All if it isn't already.
+    // Only an event the device doesn't accept right away waits for it, the rest of the frame is then written
+    // straight away again. The overflow policy applies to the events of the frame, but never to the terminating
+    // SyncEvent::All, as dropping it would merge the frame into the next one. It is left out instead if none of
+    // the events made it to the device, so that no empty frame is reported. Events over the limit set by
+    // `set_max_frame_events` are dropped, the terminating SyncEvent::All still isn't.
+    pub async fn write_frame(&mut self, frame: &Frame) -> Result<(), Error> {
+        let mut events = match frame.events().split_last() {
+            Some((Event::Sync(SyncEvent::All), events)) => events,
+            _ => frame.events(),
         };
 
-        if let (Some(code), Some(value)) = (code, value) {
-            self.write_raw(r#type as _, code, value).await?;
+        if events.len() > self.max_frame_events {
+            let truncated = events.len() - self.max_frame_events;
+            tracing::warn!(
+                "Truncated frame, dropped {} event{} over the limit of {}",
+                truncated,
+                if truncated == 1 { "" } else { "s" },
+                self.max_frame_events
+            );
+
+            events = &events[..self.max_frame_events];
+        }
+
+        let mut written = false;
+        for event in events {
+            if let Some((r#type, code, value)) = event.to_raw() {
+                written |= self.write_raw_until(r#type, code, value, None).await?;
+            }
+        }
+
+        if written {
+            self.write_waiting(glue::EV_SYN as _, glue::SYN_REPORT as _, 0, None)
+                .await?;
         }
 
         Ok(())
     }
 
+    pub fn set_max_frame_events(&mut self, max: usize) {
+        self.max_frame_events = max;
+    }
+
+    pub fn into_shared(self) -> SharedWriter {
+        SharedWriter::new(self)
+    }
+
+    // Drives the device to match the given state, for example when taking over from another device.
+    // Keys not present in the state are released, the kernel filters out the ones that didn't change.
+    pub async fn restore_state(&mut self, state: &DeviceState) -> Result<(), Error> {
+        for code in 0..glue::KEY_CNT as u16 {
+            if let Some(key) = Key::from_raw(code) {
+                self.write_raw(glue::EV_KEY as _, code, state.keys.contains(&key) as _)
+                    .await?;
+            }
+        }
+
+        for (axis, value) in &state.abs {
+            if let Some(code) = axis.to_raw() {
+                self.write_raw(glue::EV_ABS as _, code, *value).await?;
+            }
+        }
+
+        for (slot, events) in state.slots.iter().enumerate() {
+            self.write_raw(glue::EV_ABS as _, glue::ABS_MT_SLOT as _, slot as _)
+                .await?;
+
+            for event in events {
+                self.write(&Event::Abs(*event)).await?;
+            }
+        }
+
+        if let Some(slot) = state.current_slot {
+            self.write_raw(glue::EV_ABS as _, glue::ABS_MT_SLOT as _, slot)
+                .await?;
+        }
+
+        for (switch, on) in &state.switches {
+            if let Some(code) = switch.to_raw() {
+                self.write_raw(glue::EV_SW as _, code, *on as _).await?;
+            }
+        }
+
+        for (code, on) in &state.leds {
+            self.write_raw(glue::EV_LED as _, *code, *on as _).await?;
+        }
+
+        self.write_raw(glue::EV_SYN as _, glue::SYN_REPORT as _, 0)
+            .await
+    }
+
+    // Whether the device accepts the given event, the kernel silently drops those it doesn't.
+    pub fn supports(&self, r#type: EventType, code: u16) -> bool {
+        match r#type.to_raw() {
+            Some(r#type) => self.codes.contains(&(r#type, code)),
+            None => false,
+        }
+    }
+
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.overflow_policy = policy;
+    }
+
+    // Clamps absolute axis values into the range of the device,
+    // useful when forwarding from a source whose range exceeds that of the device.
+    pub fn set_abs_clamp(&mut self, clamp: bool) {
+        self.abs_clamp = clamp;
+    }
+
+    // Turns hi-res wheel scrolling into one legacy notch every `units` hi-res units (120 is one notch of a
+    // regular wheel), for applications that don't cope with smooth scrolling. 0 turns it off.
+    pub fn set_wheel_notch(&mut self, units: u32) {
+        self.wheel_notch = match units {
+            0 => None,
+            units => Some(WheelNotch::new(units.min(i32::MAX as _) as _)),
+        };
+    }
+
+    // The event device node, see `WriterBuilder::build_and_wait` for when it can be relied upon to exist.
     pub fn path(&self) -> Option<&Path> {
-        let path = unsafe { glue::libevdev_uinput_get_devnode(self.uinput.as_ptr()) };
-        if path.is_null() {
-            return None;
+        self.path.as_deref()
+    }
+
+    pub fn syspath(&self) -> Option<&Path> {
+        self.syspath.as_deref()
+    }
+
+    // Waits for the device node to show up and become accessible, which happens once udev has processed the
+    // device.
+    async fn wait_for_devnode(&self, timeout: Duration) -> Result<(), Error> {
+        let path = self
+            .path()
+            .ok_or_else(|| Error::new(ErrorKind::Other, "No devnode for writer"))?;
+        let parent = path
+            .parent()
+            .ok_or_else(|| Error::new(ErrorKind::Other, "Devnode has no parent directory"))?;
+
+        let mut inotify = Inotify::init()?;
+        inotify.add_watch(parent, WatchMask::CREATE | WatchMask::ATTRIB)?;
+
+        // Same as with readers, the watch has to be in place before checking.
+        let mut stream = inotify.event_stream([0; 512])?;
+        let wait = async {
+            while !is_accessible(path) {
+                match stream.next().await {
+                    Some(event) => event?,
+                    None => return Err(Error::new(ErrorKind::BrokenPipe, "Inotify stream ended")),
+                };
+            }
+
+            Ok(())
+        };
+
+        time::timeout(timeout, wait).await.map_err(|_| {
+            Error::new(
+                ErrorKind::TimedOut,
+                format!("Timed out waiting for {:?} to become accessible", path),
+            )
+        })?
+    }
+
+    // There is no way to set udev properties directly, so this triggers a synthetic change uevent
+    // carrying the seat as SYNTH_ARG_ID_SEAT. It only takes effect together with a udev rule such as:
+    // ENV{SYNTH_ARG_ID_SEAT}=="?*", ENV{ID_SEAT}="$env{SYNTH_ARG_ID_SEAT}", TAG+="seat"
+    async fn tag_seat(&self, seat: &str) -> Result<(), Error> {
+        let path = self
+            .syspath()
+            .ok_or_else(|| Error::new(ErrorKind::Other, "No syspath for writer"))?;
+
+        let uuid = tokio::fs::read_to_string("/proc/sys/kernel/random/uuid").await?;
+        let uevent = format!("change {} ID_SEAT={}", uuid.trim(), seat);
+
+        tokio::fs::write(path.join("uevent"), uevent).await
+    }
+
+    pub async fn wait_for_reader(&self) -> Result<(), Error> {
+        let path = self
+            .path()
+            .ok_or_else(|| Error::new(ErrorKind::Other, "No devnode for writer"))?;
+
+        let mut inotify = Inotify::init()?;
+        inotify.add_watch(path, WatchMask::OPEN)?;
+
+        // The watch has to be in place before checking for existing readers,
+        // otherwise a reader opening the device in between would be missed.
+        if has_reader(path) {
+            return Ok(());
         }
 
-        let path = unsafe { CStr::from_ptr(path) };
-        let path = OsStr::from_bytes(path.to_bytes());
-        let path = Path::new(path);
+        let mut stream = inotify.event_stream([0; 64])?;
+        match stream.next().await {
+            Some(event) => event.map(|_| ()),
+            None => Err(Error::new(ErrorKind::BrokenPipe, "Inotify stream ended")),
+        }
+    }
+
+    // Waits for userspace to change one of the LEDs of the device, for example by toggling Caps Lock.
+    // Only LEDs enabled by `WriterBuilder::led` are ever changed. Force feedback requests are answered but ignored,
+    // use `read_request` to get both.
+    pub async fn read_led(&mut self) -> Result<LedEvent, Error> {
+        future::poll_fn(|cx| self.poll_read_led(cx)).await
+    }
+
+    // Like `read_led`, for polling several writers at once.
+    pub fn poll_read_led(&mut self, cx: &mut Context<'_>) -> Poll<Result<LedEvent, Error>> {
+        loop {
+            if let DeviceRequest::Led(event) = task::ready!(self.poll_read_request(cx))? {
+                return Poll::Ready(Ok(event));
+            }
+        }
+    }
 
-        Some(path)
+    // Waits for userspace to change an LED or to make a force feedback request, see `WriterBuilder::ff`.
+    // Effect uploads are accepted as long as the effect is supported, whatever replays them doesn't report back.
+    pub async fn read_request(&mut self) -> Result<DeviceRequest, Error> {
+        future::poll_fn(|cx| self.poll_read_request(cx)).await
+    }
+
+    // Like `read_request`, for polling several writers at once.
+    pub fn poll_read_request(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<DeviceRequest, Error>> {
+        loop {
+            let event = task::ready!(self.uinput.poll_read(cx))?;
+
+            let request = match (event.type_ as _, event.code as _) {
+                (glue::EV_LED, _) => Led::from_raw(event.code).map(|led| {
+                    DeviceRequest::Led(LedEvent {
+                        led,
+                        state: event.value != 0,
+                    })
+                }),
+                (glue::EV_UINPUT, glue::UI_FF_UPLOAD) => self
+                    .uinput
+                    .ff_upload(event.value as _, |effect| {
+                        let id = effect.id;
+                        FfEffect::from_raw(effect).map(|effect| FfRequest::Upload { id, effect })
+                    })?
+                    .map(DeviceRequest::Ff),
+                (glue::EV_UINPUT, glue::UI_FF_ERASE) => {
+                    let id = self.uinput.ff_erase(event.value as _)?;
+                    Some(DeviceRequest::Ff(FfRequest::Erase { id }))
+                }
+                (glue::EV_FF, glue::FF_GAIN) => {
+                    Some(DeviceRequest::Ff(FfRequest::Gain(event.value as _)))
+                }
+                // Effect IDs are allocated below the codes of the device wide settings.
+                (glue::EV_FF, code) if code < glue::FF_GAIN => {
+                    Some(DeviceRequest::Ff(FfRequest::Play {
+                        id: event.code as _,
+                        count: event.value,
+                    }))
+                }
+                _ => None,
+            };
+
+            if let Some(request) = request {
+                return Poll::Ready(Ok(request));
+            }
+        }
     }
 
     pub(crate) async fn from_evdev(evdev: &Evdev) -> Result<Self, Error> {
+        let abs_ranges = (0..glue::ABS_CNT)
+            .filter_map(|code| {
+                let info = unsafe { glue::libevdev_get_abs_info(evdev.as_ptr(), code).as_ref() }?;
+                if info.minimum == 0 && info.maximum == 0 {
+                    return None;
+                }
+
+                Some((code as _, (info.minimum, info.maximum)))
+            })
+            .collect();
+
+        let codes = [
+            (glue::EV_SYN, glue::SYN_CNT),
+            (glue::EV_KEY, glue::KEY_CNT),
+            (glue::EV_REL, glue::REL_CNT),
+            (glue::EV_ABS, glue::ABS_CNT),
+            (glue::EV_LED, glue::LED_CNT),
+            (glue::EV_MSC, glue::MSC_CNT),
+            (glue::EV_REP, glue::REP_CNT),
+            (glue::EV_SW, glue::SW_CNT),
+        ]
+        .iter()
+        .flat_map(|(r#type, count)| (0..*count).map(move |code| (*r#type, code)))
+        .filter(|(r#type, code)| unsafe {
+            glue::libevdev_has_event_code(evdev.as_ptr(), *r#type, *code) == 1
+        })
+        .map(|(r#type, code)| (r#type as _, code as _))
+        .collect();
+
+        let uinput = Uinput::from_evdev(evdev).await?;
+        let path = |path: *const libc::c_char| {
+            if path.is_null() {
+                return None;
+            }
+
+            let path = unsafe { CStr::from_ptr(path) };
+            Some(PathBuf::from(OsStr::from_bytes(path.to_bytes())))
+        };
+
         Ok(Self {
-            uinput: Uinput::from_evdev(evdev).await?,
+            path: path(unsafe { glue::libevdev_uinput_get_devnode(uinput.as_ptr()) }),
+            syspath: path(unsafe { glue::libevdev_uinput_get_syspath(uinput.as_ptr()) }),
+            uinput,
+            overflow_policy: OverflowPolicy::default(),
+            abs_ranges,
+            abs_clamp: false,
+            wheel_notch: None,
+            wheel_synth: WheelSynth::default(),
+            codes,
+            key_sequences: HashMap::new(),
+            max_frame_events: MAX_FRAME_EVENTS,
         })
     }
 
+    fn clamp_abs(&self, code: u16, value: i32) -> i32 {
+        // A tracking ID of -1 marks a released slot and is always valid.
+        if code == glue::ABS_MT_TRACKING_ID as _ && value == -1 {
+            return value;
+        }
+
+        let (min, max) = match self.abs_ranges.get(&code) {
+            Some(range) => *range,
+            None => return value,
+        };
+
+        let clamped = value.clamp(min, max);
+        if clamped != value {
+            tracing::trace!(
+                "Clamped value {} of absolute axis {} into range {}..={}",
+                value,
+                code,
+                min,
+                max
+            );
+        }
+
+        clamped
+    }
+
     pub(crate) async fn write_raw(
         &mut self,
         r#type: u16,
         code: u16,
         value: i32,
+    ) -> Result<(), Error> {
+        self.write_raw_until(r#type, code, value, None).await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn write_raw_timeout(
+        &mut self,
+        r#type: u16,
+        code: u16,
+        value: i32,
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        self.write_raw_until(r#type, code, value, Some(Instant::now() + timeout))
+            .await?;
+
+        Ok(())
+    }
+
+    // Returns whether the event made it to the device, rather than being dropped or held back by the wheel notch.
+    async fn write_raw_until(
+        &mut self,
+        r#type: u16,
+        code: u16,
+        value: i32,
+        deadline: Option<Instant>,
+    ) -> Result<bool, Error> {
+        let (code, value) = match &mut self.wheel_notch {
+            Some(notch) if r#type == glue::EV_REL as _ => match notch.map(code, value) {
+                Some(event) => event,
+                None => return Ok(false),
+            },
+            _ => (code, value),
+        };
+
+        let counterpart = if r#type == glue::EV_REL as _ {
+            self.wheel_synth.map(code, value)
+        } else {
+            None
+        };
+
+        let mut written = self.write_mapped(r#type, code, value, deadline).await?;
+        if let Some((code, value)) = counterpart {
+            written |= self.write_mapped(r#type, code, value, deadline).await?;
+        }
+
+        Ok(written)
+    }
+
+    async fn write_mapped(
+        &mut self,
+        r#type: u16,
+        code: u16,
+        value: i32,
+        deadline: Option<Instant>,
+    ) -> Result<bool, Error> {
+        let value = if self.abs_clamp && r#type == glue::EV_ABS as _ {
+            self.clamp_abs(code, value)
+        } else {
+            value
+        };
+
+        // The device is writable most of the time, so try writing straight away and only wait if it would block.
+        match self.write_event(r#type, code, value) {
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+            result => return result.map(|_| true),
+        }
+
+        if self.overflow_policy.drops(r#type, code) {
+            tracing::trace!(
+                "Dropped event {}:{}:{}, device is not keeping up",
+                r#type,
+                code,
+                value
+            );
+
+            return Ok(false);
+        }
+
+        self.write_waiting(r#type, code, value, deadline).await?;
+
+        Ok(true)
+    }
+
+    // Waits for the device to accept the event, regardless of the overflow policy.
+    async fn write_waiting(
+        &self,
+        r#type: u16,
+        code: u16,
+        value: i32,
+        deadline: Option<Instant>,
     ) -> Result<(), Error> {
         loop {
-            let result = self.uinput.file().writable().await?.try_io(|_| {
-                let ret = unsafe {
-                    glue::libevdev_uinput_write_event(
-                        self.uinput.as_ptr(),
-                        r#type as _,
-                        code as _,
-                        value,
+            let writable = self.uinput.file().writable();
+            let mut guard = match deadline {
+                Some(deadline) => time::timeout_at(deadline, writable).await.map_err(|_| {
+                    Error::new(
+                        ErrorKind::TimedOut,
+                        "Device did not become writable in time",
                     )
-                };
-
-                if ret < 0 {
-                    return Err(Error::from_raw_os_error(-ret).into());
-                }
+                })??,
+                None => writable.await?,
+            };
 
-                Ok(())
-            });
+            let result = guard.try_io(|_| self.write_event(r#type, code, value));
 
             match result {
                 Ok(result) => return result,
-                Err(_) => continue, // This means it would block.
+                // It would block. try_io cleared the readiness, so waiting again doesn't return right away.
+                Err(_) => continue,
+            }
+        }
+    }
+
+    fn write_event(&self, r#type: u16, code: u16, value: i32) -> Result<(), Error> {
+        let ret = unsafe {
+            glue::libevdev_uinput_write_event(self.uinput.as_ptr(), r#type as _, code as _, value)
+        };
+
+        if ret < 0 {
+            return Err(Error::from_raw_os_error(-ret));
+        }
+
+        Ok(())
+    }
+}
+
+struct WheelNotch {
+    units: i32,
+    // Indexed by 0 for the vertical and 1 for the horizontal wheel.
+    remainder: [i32; 2],
+    hi_res: [bool; 2],
+}
+
+impl WheelNotch {
+    fn new(units: i32) -> Self {
+        Self {
+            units,
+            remainder: [0; 2],
+            hi_res: [false; 2],
+        }
+    }
+
+    // Returns the event to write instead, if any.
+    fn map(&mut self, code: u16, value: i32) -> Option<(u16, i32)> {
+        let (wheel, legacy) = match code as u32 {
+            glue::REL_WHEEL_HI_RES => (0, glue::REL_WHEEL),
+            glue::REL_HWHEEL_HI_RES => (1, glue::REL_HWHEEL),
+            // Hi-res devices report the legacy notches alongside, which would scroll twice.
+            // Those without hi-res support only report notches, which are passed through.
+            glue::REL_WHEEL if self.hi_res[0] => return None,
+            glue::REL_HWHEEL if self.hi_res[1] => return None,
+            _ => return Some((code, value)),
+        };
+
+        self.hi_res[wheel] = true;
+
+        // Division truncates towards zero, so the remainder keeps the direction of the scroll.
+        let remainder = self.remainder[wheel].saturating_add(value);
+        let notches = remainder / self.units;
+        self.remainder[wheel] = remainder % self.units;
+
+        if notches == 0 {
+            return None;
+        }
+
+        Some((legacy as _, notches))
+    }
+}
+
+// The legacy and hi-res code of the vertical and the horizontal wheel.
+const WHEELS: [(u32, u32); 2] = [
+    (glue::REL_WHEEL, glue::REL_WHEEL_HI_RES),
+    (glue::REL_HWHEEL, glue::REL_HWHEEL_HI_RES),
+];
+
+// One notch of a regular wheel in hi-res units.
+const HI_RES_NOTCH: i32 = 120;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum WheelSource {
+    Legacy,
+    HiRes,
+}
+
+// Applications expect to see both the legacy and the hi-res wheel events, so for devices only reporting one
+// of them, the other one is synthesized.
+#[derive(Debug, Default)]
+struct WheelSynth {
+    // Indexed like `WHEELS`, None if the device reports both kinds or has no such wheel.
+    sources: [Option<WheelSource>; 2],
+    remainder: [i32; 2],
+}
+
+impl WheelSynth {
+    // Returns the counterpart to write along with the event, if any.
+    fn map(&mut self, code: u16, value: i32) -> Option<(u16, i32)> {
+        let (wheel, (legacy, hi_res)) = WHEELS
+            .iter()
+            .enumerate()
+            .find(|(_, (legacy, hi_res))| code as u32 == *legacy || code as u32 == *hi_res)?;
+
+        match self.sources[wheel]? {
+            WheelSource::Legacy if code as u32 == *legacy => {
+                Some((*hi_res as _, value.saturating_mul(HI_RES_NOTCH)))
+            }
+            WheelSource::HiRes if code as u32 == *hi_res => {
+                // Scrolling back shouldn't have to make up for what was left over from the other direction first.
+                if (self.remainder[wheel] < 0) != (value < 0) {
+                    self.remainder[wheel] = 0;
+                }
+
+                let remainder = self.remainder[wheel].saturating_add(value);
+                let notches = remainder / HI_RES_NOTCH;
+                self.remainder[wheel] = remainder % HI_RES_NOTCH;
+
+                if notches == 0 {
+                    return None;
+                }
+
+                Some((*legacy as _, notches))
             }
+            _ => None,
         }
     }
 }
 
+// Whether the device node can be opened for reading and writing, an access(2) check with R_OK | W_OK.
+fn is_accessible(path: &Path) -> bool {
+    let path = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+
+    // Not opening the device, which would look like a reader to `wait_for_reader`.
+    unsafe { libc::access(path.as_ptr(), libc::R_OK | libc::W_OK) == 0 }
+}
+
+// Checks whether any other process has the device node open by scanning /proc/<pid>/fd.
+// Processes we don't have permissions to inspect are skipped.
+fn has_reader(path: &Path) -> bool {
+    let self_pid = std::process::id().to_string();
+
+    let processes = match fs::read_dir("/proc") {
+        Ok(processes) => processes,
+        Err(_) => return false,
+    };
+
+    processes
+        .filter_map(Result::ok)
+        .filter(|process| process.file_name() != self_pid.as_str())
+        .filter_map(|process| fs::read_dir(process.path().join("fd")).ok())
+        .flatten()
+        .filter_map(Result::ok)
+        .any(|fd| matches!(fs::read_link(fd.path()), Ok(target) if target == path))
+}
+
 pub struct WriterBuilder {
     evdev: Evdev,
+    seat: Option<String>,
 }
 
 impl WriterBuilder {
@@ -107,10 +789,158 @@ impl WriterBuilder {
             glue::libevdev_set_id_bustype(evdev.as_ptr(), glue::BUS_VIRTUAL as _);
         }
 
-        Ok(Self { evdev })
+        // SYN_REPORT delimits every frame, so it has to be enabled regardless of the device.
+        let ret = unsafe {
+            glue::libevdev_enable_event_code(
+                evdev.as_ptr(),
+                glue::EV_SYN,
+                glue::SYN_REPORT,
+                ptr::null(),
+            )
+        };
+
+        if ret < 0 {
+            return Err(Error::from_raw_os_error(-ret));
+        }
+
+        Ok(Self { evdev, seat: None })
+    }
+
+    // Impersonates a well known device, including its bus type.
+    pub fn from_profile(profile: DeviceProfile) -> Result<Self, Error> {
+        let builder = Self::new()?;
+
+        unsafe {
+            glue::libevdev_set_id_bustype(builder.evdev.as_ptr(), profile.bus_type() as _);
+        }
+
+        builder
+            .name(profile.device_name())
+            .vendor(profile.vendor())
+            .product(profile.product())
+            .version(profile.version())
+            .rel(profile.rel())?
+            .abs(profile.abs())?
+            .key(profile.keys())
     }
 
+    // Enables every code and property the device supports, absolute axes keep their whole info and auto repeat
+    // its delay and period. The identity of the device isn't copied.
+    pub fn clone_from(interceptor: &Interceptor) -> Result<Self, Error> {
+        let builder = Self::new()?;
+        let source = interceptor.evdev();
+
+        let types = [
+            (glue::EV_SYN, glue::SYN_CNT),
+            (glue::EV_REL, glue::REL_CNT),
+            (glue::EV_ABS, glue::ABS_CNT),
+            (glue::EV_KEY, glue::KEY_CNT),
+            (glue::EV_MSC, glue::MSC_CNT),
+            (glue::EV_SW, glue::SW_CNT),
+            (glue::EV_LED, glue::LED_CNT),
+        ];
+
+        for (r#type, count) in types.iter().copied() {
+            for code in 0..count {
+                if unsafe { glue::libevdev_has_event_code(source.as_ptr(), r#type, code) } != 1 {
+                    continue;
+                }
+
+                let data = if r#type == glue::EV_ABS {
+                    unsafe { glue::libevdev_get_abs_info(source.as_ptr(), code) as *const _ }
+                } else {
+                    ptr::null()
+                };
+
+                let ret = unsafe {
+                    glue::libevdev_enable_event_code(builder.evdev.as_ptr(), r#type, code, data)
+                };
+
+                if ret < 0 {
+                    return Err(Error::from_raw_os_error(-ret));
+                }
+            }
+        }
+
+        for property in 0..glue::INPUT_PROP_CNT {
+            if unsafe { glue::libevdev_has_property(source.as_ptr(), property) } != 1 {
+                continue;
+            }
+
+            let ret = unsafe { glue::libevdev_enable_property(builder.evdev.as_ptr(), property) };
+            if ret < 0 {
+                return Err(Error::from_raw_os_error(-ret));
+            }
+        }
+
+        // Neither can be enabled with a null data pointer like the codes above, EV_FF also needs FF_GAIN.
+        let builder = builder.ff(interceptor.ff_codes())?;
+
+        match interceptor.repeat() {
+            Some((delay, period)) => builder.repeat(delay, period),
+            None => Ok(builder),
+        }
+    }
+
+    // Recreates a device described by `Interceptor::capabilities`, identity included.
+    pub fn from_capabilities(capabilities: &DeviceCapabilities) -> Result<Self, Error> {
+        let builder = Self::new()?
+            .name(&capabilities.name)
+            .vendor(capabilities.vendor)
+            .product(capabilities.product)
+            .version(capabilities.version)
+            .rel(capabilities.rel.iter().copied())?
+            .abs(capabilities.abs.iter().map(|(axis, info)| (*axis, *info)))?
+            .key(capabilities.keys.iter().copied())?
+            .syn(capabilities.syn.iter().copied())?
+            .misc(capabilities.msc.iter().copied())?
+            .led(capabilities.leds.iter().copied())?
+            .ff(capabilities.ff.iter().copied())?
+            .switch(capabilities.switches.iter().copied())?
+            .props(capabilities.props.iter().copied())?;
+
+        match capabilities.repeat {
+            Some((delay, period)) => builder.repeat(delay, period),
+            None => Ok(builder),
+        }
+    }
+
+    // Names longer than MAX_NAME_LEN are truncated, see `try_name` for a strict alternative.
     pub fn name(self, name: &CStr) -> Self {
+        let bytes = name.to_bytes();
+        if bytes.len() <= MAX_NAME_LEN {
+            return self.set_name(name);
+        }
+
+        // Don't cut a multibyte UTF-8 sequence in half.
+        let mut len = MAX_NAME_LEN;
+        while len > 0 && bytes[len] & 0b1100_0000 == 0b1000_0000 {
+            len -= 1;
+        }
+
+        tracing::warn!(
+            "Device name {:?} is longer than {} bytes, truncating it",
+            name,
+            MAX_NAME_LEN
+        );
+
+        // The bytes come from a CStr, so there can be no nul bytes among them.
+        let truncated = CString::new(&bytes[..len]).unwrap();
+        self.set_name(&truncated)
+    }
+
+    pub fn try_name(self, name: &CStr) -> Result<Self, Error> {
+        if name.to_bytes().len() > MAX_NAME_LEN {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Device name is longer than {} bytes", MAX_NAME_LEN),
+            ));
+        }
+
+        Ok(self.set_name(name))
+    }
+
+    fn set_name(self, name: &CStr) -> Self {
         unsafe {
             glue::libevdev_set_name(self.evdev.as_ptr(), name.as_ptr());
         }
@@ -142,6 +972,12 @@ impl WriterBuilder {
         self
     }
 
+    // Assigns the device to a logind seat, see `Writer::tag_seat` for what this requires.
+    pub fn seat_tag(mut self, seat: &str) -> Self {
+        self.seat = Some(seat.to_owned());
+        self
+    }
+
     pub fn rel<T: IntoIterator<Item = RelAxis>>(self, items: T) -> Result<Self, Error> {
         for axis in items {
             let axis = match axis.to_raw() {
@@ -166,20 +1002,31 @@ impl WriterBuilder {
         Ok(self)
     }
 
-    pub fn abs<T: IntoIterator<Item = (AbsAxis, AbsInfo)>>(self, items: T) -> Result<Self, Error> {
-        let ret = unsafe {
-            glue::libevdev_enable_event_code(
-                self.evdev.as_ptr(),
-                glue::EV_SYN,
-                glue::SYN_MT_REPORT,
-                ptr::null(),
-            )
-        };
+    pub fn syn<T: IntoIterator<Item = SyncEvent>>(self, items: T) -> Result<Self, Error> {
+        for event in items {
+            let code = match event.to_raw() {
+                Some(code) => code,
+                None => continue,
+            };
 
-        if ret < 0 {
-            return Err(Error::from_raw_os_error(-ret));
+            let ret = unsafe {
+                glue::libevdev_enable_event_code(
+                    self.evdev.as_ptr(),
+                    glue::EV_SYN,
+                    code as _,
+                    ptr::null(),
+                )
+            };
+
+            if ret < 0 {
+                return Err(Error::from_raw_os_error(-ret));
+            }
         }
 
+        Ok(self)
+    }
+
+    pub fn abs<T: IntoIterator<Item = (AbsAxis, AbsInfo)>>(self, items: T) -> Result<Self, Error> {
         for (axis, info) in items {
             let code = match axis.to_raw() {
                 Some(code) => code,
@@ -212,6 +1059,77 @@ impl WriterBuilder {
         Ok(self)
     }
 
+    // Enables what the given multitouch protocol needs besides the per contact axes:
+    // SYN_MT_REPORT for protocol A, ABS_MT_SLOT with room for the given number of contacts and ABS_MT_TRACKING_ID
+    // for protocol B. Protocol A devices don't limit the number of contacts.
+    // The number of contacts is the number of slots, not the highest slot index, ABS_MT_SLOT ends up with a maximum
+    // of one less as the kernel expects. There has to be at least one.
+    pub fn mt_protocol(self, protocol: MtProtocol, contacts: i32) -> Result<Self, Error> {
+        if contacts < 1 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid number of contacts {}", contacts),
+            ));
+        }
+
+        let range = |max| AbsInfo {
+            min: 0,
+            max,
+            fuzz: 0,
+            flat: 0,
+            resolution: 0,
+        };
+
+        match protocol {
+            MtProtocol::A => self.syn([SyncEvent::Mt]),
+            MtProtocol::B => self.abs([
+                (AbsAxis::MtSlot, range(contacts - 1)),
+                (AbsAxis::MtTrackingId, range(u16::MAX as _)),
+            ]),
+        }
+    }
+
+    // Sets up a protocol B multitouch touchscreen with the given size and number of slots, enabling:
+    // - ABS_X and ABS_Y in 0..width and 0..height for the single touch emulation
+    // - ABS_MT_SLOT, ABS_MT_TRACKING_ID, ABS_MT_POSITION_X and ABS_MT_POSITION_Y
+    // - BTN_TOUCH
+    // - INPUT_PROP_DIRECT, so that userspace maps touches directly to the screen
+    pub fn touchscreen(self, width: i32, height: i32, max_slots: i32) -> Result<Self, Error> {
+        let range = |max| AbsInfo {
+            min: 0,
+            max,
+            fuzz: 0,
+            flat: 0,
+            resolution: 0,
+        };
+
+        self.props([InputProp::Direct])?
+            .mt_protocol(MtProtocol::B, max_slots)?
+            .abs([
+                (AbsAxis::X, range(width - 1)),
+                (AbsAxis::Y, range(height - 1)),
+                (AbsAxis::MtPositionX, range(width - 1)),
+                (AbsAxis::MtPositionY, range(height - 1)),
+            ])?
+            .key([Key::Button(Button::Touch)])
+    }
+
+    pub fn props<T: IntoIterator<Item = InputProp>>(self, items: T) -> Result<Self, Error> {
+        for prop in items {
+            let prop = match prop.to_raw() {
+                Some(prop) => prop,
+                None => continue,
+            };
+
+            let ret = unsafe { glue::libevdev_enable_property(self.evdev.as_ptr(), prop as _) };
+            if ret < 0 {
+                return Err(Error::from_raw_os_error(-ret));
+            }
+        }
+
+        Ok(self)
+    }
+
     pub fn key<T: IntoIterator<Item = Key>>(self, items: T) -> Result<Self, Error> {
         for key in items {
             let key = match key.to_raw() {
@@ -236,7 +1154,279 @@ impl WriterBuilder {
         Ok(self)
     }
 
+    pub fn led<T: IntoIterator<Item = Led>>(self, items: T) -> Result<Self, Error> {
+        for led in items {
+            let led = match led.to_raw() {
+                Some(led) => led,
+                None => continue,
+            };
+
+            let ret = unsafe {
+                glue::libevdev_enable_event_code(
+                    self.evdev.as_ptr(),
+                    glue::EV_LED,
+                    led as _,
+                    ptr::null(),
+                )
+            };
+
+            if ret < 0 {
+                return Err(Error::from_raw_os_error(-ret));
+            }
+        }
+
+        Ok(self)
+    }
+
+    // Requests for the enabled effect types are read with `Writer::read_request`. The gain can be set as soon as
+    // any effect type is enabled, the number of effects the device holds at once is up to libevdev.
+    pub fn ff<T: IntoIterator<Item = FfEffectType>>(self, items: T) -> Result<Self, Error> {
+        let codes = items
+            .into_iter()
+            .filter_map(|effect| effect.to_raw())
+            .map(|code| code as u32)
+            .collect::<Vec<_>>();
+
+        if codes.is_empty() {
+            return Ok(self);
+        }
+
+        for code in codes.into_iter().chain([glue::FF_GAIN]) {
+            let ret = unsafe {
+                glue::libevdev_enable_event_code(
+                    self.evdev.as_ptr(),
+                    glue::EV_FF,
+                    code,
+                    ptr::null(),
+                )
+            };
+
+            if ret < 0 {
+                return Err(Error::from_raw_os_error(-ret));
+            }
+        }
+
+        Ok(self)
+    }
+
+    // Switches start out off, the state of the device being recreated has to be written separately,
+    // see `Interceptor::snapshot_state`.
+    pub fn switch<T: IntoIterator<Item = Switch>>(self, items: T) -> Result<Self, Error> {
+        for switch in items {
+            let switch = match switch.to_raw() {
+                Some(switch) => switch,
+                None => continue,
+            };
+
+            let ret = unsafe {
+                glue::libevdev_enable_event_code(
+                    self.evdev.as_ptr(),
+                    glue::EV_SW,
+                    switch as _,
+                    ptr::null(),
+                )
+            };
+
+            if ret < 0 {
+                return Err(Error::from_raw_os_error(-ret));
+            }
+        }
+
+        Ok(self)
+    }
+
+    pub fn misc<T: IntoIterator<Item = Msc>>(self, items: T) -> Result<Self, Error> {
+        for msc in items {
+            let msc = match msc.to_raw() {
+                Some(msc) => msc,
+                None => continue,
+            };
+
+            let ret = unsafe {
+                glue::libevdev_enable_event_code(
+                    self.evdev.as_ptr(),
+                    glue::EV_MSC,
+                    msc as _,
+                    ptr::null(),
+                )
+            };
+
+            if ret < 0 {
+                return Err(Error::from_raw_os_error(-ret));
+            }
+        }
+
+        Ok(self)
+    }
+
+    // Enables kernel auto repeat of held keys with the given delay and period in milliseconds.
+    // Like EV_ABS codes and unlike the others, EV_REP codes are enabled with data, a pointer to an int holding the value.
+    pub fn repeat(self, delay: i32, period: i32) -> Result<Self, Error> {
+        for (code, value) in [(glue::REP_DELAY, delay), (glue::REP_PERIOD, period)].iter() {
+            let ret = unsafe {
+                glue::libevdev_enable_event_code(
+                    self.evdev.as_ptr(),
+                    glue::EV_REP,
+                    *code,
+                    value as *const i32 as *const _,
+                )
+            };
+
+            if ret < 0 {
+                return Err(Error::from_raw_os_error(-ret));
+            }
+        }
+
+        Ok(self)
+    }
+
     pub async fn build(self) -> Result<Writer, Error> {
-        Writer::from_evdev(&self.evdev).await
+        let mut wheel_synth = WheelSynth::default();
+        for (wheel, (legacy, hi_res)) in WHEELS.iter().enumerate() {
+            let has = |code| unsafe {
+                glue::libevdev_has_event_code(self.evdev.as_ptr(), glue::EV_REL, code) == 1
+            };
+
+            let (source, missing) = match (has(*legacy), has(*hi_res)) {
+                (true, false) => (WheelSource::Legacy, *hi_res),
+                (false, true) => (WheelSource::HiRes, *legacy),
+                _ => continue,
+            };
+
+            let ret = unsafe {
+                glue::libevdev_enable_event_code(
+                    self.evdev.as_ptr(),
+                    glue::EV_REL,
+                    missing,
+                    ptr::null(),
+                )
+            };
+
+            if ret < 0 {
+                return Err(Error::from_raw_os_error(-ret));
+            }
+
+            wheel_synth.sources[wheel] = Some(source);
+        }
+
+        let mut writer = Writer::from_evdev(&self.evdev).await?;
+        writer.wheel_synth = wheel_synth;
+
+        if let Some(seat) = &self.seat {
+            writer.tag_seat(seat).await?;
+        }
+
+        Ok(writer)
+    }
+
+    // Like `build`, but only returns once the device node exists and is accessible, so that it can be handed to
+    // other programs right away. Fails with ErrorKind::TimedOut if that doesn't happen in time.
+    pub async fn build_and_wait(self, timeout: Duration) -> Result<Writer, Error> {
+        let writer = self.build().await?;
+        writer.wait_for_devnode(timeout).await?;
+
+        Ok(writer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::key::Keyboard;
+
+    #[test]
+    fn wheel_synth() {
+        let wheel = glue::REL_WHEEL as u16;
+        let hi_res = glue::REL_WHEEL_HI_RES as u16;
+        let hwheel = glue::REL_HWHEEL_HI_RES as u16;
+
+        let mut synth = WheelSynth {
+            sources: [Some(WheelSource::HiRes), Some(WheelSource::Legacy)],
+            ..Default::default()
+        };
+
+        assert_eq!(synth.map(hi_res, 60), None);
+        assert_eq!(synth.map(hi_res, 90), Some((wheel, 1)));
+        assert_eq!(synth.map(hi_res, 250), Some((wheel, 2)));
+        // The 40 units left over are discarded when scrolling back.
+        assert_eq!(synth.map(hi_res, -100), None);
+        assert_eq!(synth.map(hi_res, -20), Some((wheel, -1)));
+        assert_eq!(synth.map(wheel, 1), None);
+
+        assert_eq!(synth.map(glue::REL_HWHEEL as _, -2), Some((hwheel, -240)));
+        assert_eq!(synth.map(hwheel, 120), None);
+        assert_eq!(synth.map(glue::REL_X as _, 5), None);
+    }
+
+    #[test]
+    fn mt_contacts() {
+        for contacts in [0, -1, i32::MIN] {
+            let err = WriterBuilder::new()
+                .unwrap()
+                .mt_protocol(MtProtocol::B, contacts)
+                .err()
+                .unwrap();
+
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+
+        assert!(WriterBuilder::new()
+            .unwrap()
+            .mt_protocol(MtProtocol::B, 1)
+            .is_ok());
+    }
+
+    // Needs access to /dev/uinput and to the device node created for the writer.
+    #[tokio::test]
+    #[ignore]
+    async fn clone_from() {
+        let source = WriterBuilder::new()
+            .unwrap()
+            .key([Key::Key(Keyboard::A)])
+            .unwrap()
+            .led([Led::CapsLock])
+            .unwrap()
+            .ff([FfEffectType::Rumble])
+            .unwrap()
+            .repeat(250, 33)
+            .unwrap()
+            .build_and_wait(Duration::from_secs(5))
+            .await
+            .unwrap();
+
+        let interceptor = Interceptor::open_observer(source.path().unwrap())
+            .await
+            .unwrap();
+        let clone = WriterBuilder::clone_from(&interceptor).unwrap();
+
+        let has = |r#type, code| unsafe {
+            glue::libevdev_has_event_code(clone.evdev.as_ptr(), r#type, code) == 1
+        };
+
+        assert!(has(glue::EV_KEY, glue::KEY_A));
+        assert!(has(glue::EV_LED, glue::LED_CAPSL));
+        assert!(has(glue::EV_FF, glue::FF_RUMBLE));
+        assert!(has(glue::EV_FF, glue::FF_GAIN));
+
+        let mut delay = 0;
+        let mut period = 0;
+        unsafe { glue::libevdev_get_repeat(clone.evdev.as_ptr(), &mut delay, &mut period) };
+        assert_eq!((delay, period), (250, 33));
+    }
+
+    // Needs access to /dev/uinput.
+    #[tokio::test]
+    #[ignore]
+    async fn supports_switches() {
+        let writer = WriterBuilder::new()
+            .unwrap()
+            .switch([Switch::Lid])
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        assert!(writer.supports(EventType::Switch, glue::SW_LID as _));
+        assert!(!writer.supports(EventType::Switch, glue::SW_TABLET_MODE as _));
     }
 }
@@ -0,0 +1,32 @@
+use crate::event::Event;
+use crate::frame::Frame;
+use crate::writer::Writer;
+
+use std::io::Error;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// A cloneable handle for writing to a single device from several producers.
+// Every call holds the lock for its whole duration, so frames written by different producers are never interleaved,
+// while events written one by one with `write` may be. Dropping a `write_frame` future midway leaves the frame
+// incomplete, just like with a plain writer.
+#[derive(Clone)]
+pub struct SharedWriter {
+    writer: Arc<Mutex<Writer>>,
+}
+
+impl SharedWriter {
+    pub(super) fn new(writer: Writer) -> Self {
+        Self {
+            writer: Arc::new(Mutex::new(writer)),
+        }
+    }
+
+    pub async fn write(&self, event: &Event) -> Result<(), Error> {
+        self.writer.lock().await.write(event).await
+    }
+
+    pub async fn write_frame(&self, frame: &Frame) -> Result<(), Error> {
+        self.writer.lock().await.write_frame(frame).await
+    }
+}
@@ -0,0 +1,333 @@
+use rkvm_input::abs::{AbsAxis, AbsEvent};
+use rkvm_input::event::Event;
+use rkvm_input::frame::Frame;
+use rkvm_input::key::{Key, KeyEvent};
+use rkvm_input::switch::{Switch, SwitchEvent};
+use rkvm_input::sync::SyncEvent;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use thiserror::Error;
+
+// A frame with the stateful events that didn't change since the previous frame left out.
+// Keyframes carry the whole state instead, so that the decoder can recover from lost frames.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DeltaFrame {
+    pub sequence: u64,
+    pub keyframe: bool,
+    pub events: Vec<Event>,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum DeltaError {
+    #[error(
+        "Missed frames (expected sequence number {expected}, got {got}), waiting for a keyframe"
+    )]
+    Gap { expected: u64, got: u64 },
+    #[error("No keyframe received yet")]
+    NoKeyframe,
+}
+
+pub struct FrameDeltaEncoder {
+    state: State,
+    sequence: u64,
+    keyframe_interval: u64,
+    since_keyframe: u64,
+    keyframe_requested: bool,
+}
+
+impl FrameDeltaEncoder {
+    // Every `keyframe_interval`th frame is a keyframe, 0 means only the first one and requested ones are.
+    pub fn new(keyframe_interval: u64) -> Self {
+        Self {
+            state: State::default(),
+            sequence: 0,
+            keyframe_interval,
+            since_keyframe: 0,
+            keyframe_requested: true,
+        }
+    }
+
+    // Makes the next frame a keyframe, meant to be called when the decoder reports an error.
+    pub fn request_keyframe(&mut self) {
+        self.keyframe_requested = true;
+    }
+
+    pub fn encode(&mut self, frame: &Frame) -> DeltaFrame {
+        let keyframe = self.keyframe_requested
+            || (self.keyframe_interval != 0 && self.since_keyframe + 1 >= self.keyframe_interval);
+
+        let events = if keyframe {
+            // The state is captured before the frame is applied to it, so that the events of the frame keep
+            // referring to the same multitouch slot they did originally.
+            let mut events = self.state.events();
+            for event in frame {
+                self.state.apply(event);
+                events.push(event.clone());
+            }
+
+            events
+        } else if frame
+            .iter()
+            .any(|event| *event == Event::Sync(SyncEvent::Mt))
+        {
+            // Protocol A devices report every contact in full each frame, so there is nothing to leave out.
+            frame.iter().cloned().collect()
+        } else {
+            frame
+                .iter()
+                .filter(|event| self.state.apply(event))
+                .cloned()
+                .collect()
+        };
+
+        let delta = DeltaFrame {
+            sequence: self.sequence,
+            keyframe,
+            events,
+        };
+
+        self.sequence += 1;
+        self.keyframe_requested = false;
+        self.since_keyframe = if keyframe { 0 } else { self.since_keyframe + 1 };
+
+        delta
+    }
+}
+
+// The decoder only needs to track the sequence numbers, the device it writes to holds the state.
+pub struct FrameDeltaDecoder {
+    // None until the first keyframe and after a gap.
+    next_sequence: Option<u64>,
+}
+
+impl FrameDeltaDecoder {
+    pub fn new() -> Self {
+        Self {
+            next_sequence: None,
+        }
+    }
+
+    // On error, the delta frame is discarded and the encoder should be asked for a keyframe.
+    pub fn decode(&mut self, delta: DeltaFrame) -> Result<Frame, DeltaError> {
+        if !delta.keyframe {
+            match self.next_sequence {
+                Some(expected) if expected == delta.sequence => {}
+                Some(expected) => {
+                    self.next_sequence = None;
+
+                    return Err(DeltaError::Gap {
+                        expected,
+                        got: delta.sequence,
+                    });
+                }
+                None => return Err(DeltaError::NoKeyframe),
+            }
+        }
+
+        self.next_sequence = Some(delta.sequence + 1);
+
+        Ok(delta.events.into())
+    }
+}
+
+impl Default for FrameDeltaDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Code {
+    Key(Key),
+    Abs(AbsAxis),
+    // Multitouch axes are tracked per slot.
+    Mt(Option<i32>, AbsAxis),
+    MtToolType(Option<i32>),
+    Switch(Switch),
+}
+
+#[derive(Default, Debug, PartialEq, Eq)]
+struct State {
+    values: HashMap<Code, Event>,
+    slot: Option<i32>,
+}
+
+impl State {
+    // Returns whether the event changed the state, events without state always do.
+    fn apply(&mut self, event: &Event) -> bool {
+        let code = match event {
+            Event::Key(KeyEvent { key, .. }) => Code::Key(*key),
+            Event::Abs(AbsEvent::Axis {
+                axis: AbsAxis::MtSlot,
+                value,
+            }) => {
+                self.slot = Some(*value);
+                Code::Abs(AbsAxis::MtSlot)
+            }
+            Event::Abs(AbsEvent::Axis { axis, .. }) if is_mt(axis) => Code::Mt(self.slot, *axis),
+            Event::Abs(AbsEvent::Axis { axis, .. }) => Code::Abs(*axis),
+            Event::Abs(AbsEvent::MtToolType { .. }) => Code::MtToolType(self.slot),
+            Event::Switch(SwitchEvent { switch, .. }) => Code::Switch(*switch),
+            _ => return true,
+        };
+
+        self.values.insert(code, event.clone()).as_ref() != Some(event)
+    }
+
+    // Events reproducing the whole state, ending in the current slot.
+    fn events(&self) -> Vec<Event> {
+        let slot_event = |value| {
+            Event::Abs(AbsEvent::Axis {
+                axis: AbsAxis::MtSlot,
+                value,
+            })
+        };
+
+        let mut slots = HashMap::<i32, Vec<Event>>::new();
+        let mut events = Vec::new();
+
+        for (code, event) in &self.values {
+            match code {
+                Code::Key(_) | Code::Abs(_) | Code::Switch(_)
+                    if *code != Code::Abs(AbsAxis::MtSlot) =>
+                {
+                    events.push(event.clone())
+                }
+                Code::Mt(Some(slot), _) | Code::MtToolType(Some(slot)) => {
+                    slots.entry(*slot).or_default().push(event.clone())
+                }
+                // Single slot devices never report a slot.
+                Code::Mt(None, _) | Code::MtToolType(None) => events.push(event.clone()),
+                _ => {}
+            }
+        }
+
+        for (slot, slot_events) in slots {
+            events.push(slot_event(slot));
+            events.extend(slot_events);
+        }
+
+        if let Some(slot) = self.slot {
+            events.push(slot_event(slot));
+        }
+
+        events
+    }
+}
+
+fn is_mt(axis: &AbsAxis) -> bool {
+    matches!(
+        axis,
+        AbsAxis::MtSlot
+            | AbsAxis::MtTouchMajor
+            | AbsAxis::MtTouchMinor
+            | AbsAxis::MtWidthMajor
+            | AbsAxis::MtWidthMinor
+            | AbsAxis::MtOrientation
+            | AbsAxis::MtPositionX
+            | AbsAxis::MtPositionY
+            | AbsAxis::MtBlobId
+            | AbsAxis::MtTrackingId
+            | AbsAxis::MtPressure
+            | AbsAxis::MtDistance
+            | AbsAxis::MtToolX
+            | AbsAxis::MtToolY
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rkvm_input::key::Keyboard;
+    use rkvm_input::rel::{RelAxis, RelEvent};
+
+    fn frames() -> Vec<Frame> {
+        let abs = |axis, value| Event::Abs(AbsEvent::Axis { axis, value });
+        let key = |down| {
+            Event::Key(KeyEvent {
+                key: Key::Key(Keyboard::A),
+                down,
+            })
+        };
+        let sync = Event::Sync(SyncEvent::All);
+
+        vec![
+            vec![abs(AbsAxis::X, 10), abs(AbsAxis::Y, 20), sync.clone()],
+            vec![
+                abs(AbsAxis::X, 10),
+                abs(AbsAxis::Y, 21),
+                key(true),
+                sync.clone(),
+            ],
+            vec![
+                abs(AbsAxis::MtSlot, 1),
+                abs(AbsAxis::MtPositionX, 5),
+                abs(AbsAxis::X, 10),
+                sync.clone(),
+            ],
+            vec![
+                Event::Rel(RelEvent {
+                    axis: RelAxis::X,
+                    value: 1,
+                }),
+                key(true),
+                sync.clone(),
+            ],
+            vec![
+                abs(AbsAxis::MtSlot, 0),
+                abs(AbsAxis::MtPositionX, 5),
+                key(false),
+                sync,
+            ],
+        ]
+        .into_iter()
+        .map(Frame::from)
+        .collect()
+    }
+
+    #[test]
+    fn round_trip_preserves_state() {
+        let mut encoder = FrameDeltaEncoder::new(3);
+        let mut decoder = FrameDeltaDecoder::new();
+        let mut raw = State::default();
+        let mut decoded = State::default();
+
+        for frame in frames() {
+            for event in &frame {
+                raw.apply(event);
+            }
+
+            let frame = decoder.decode(encoder.encode(&frame)).unwrap();
+            for event in &frame {
+                decoded.apply(event);
+            }
+
+            assert_eq!(raw, decoded);
+        }
+    }
+
+    #[test]
+    fn gap_requires_keyframe() {
+        let mut encoder = FrameDeltaEncoder::new(0);
+        let mut decoder = FrameDeltaDecoder::new();
+        let frames = frames();
+
+        decoder.decode(encoder.encode(&frames[0])).unwrap();
+        encoder.encode(&frames[1]);
+
+        assert_eq!(
+            decoder.decode(encoder.encode(&frames[2])),
+            Err(DeltaError::Gap {
+                expected: 1,
+                got: 2
+            })
+        );
+        assert_eq!(
+            decoder.decode(encoder.encode(&frames[3])),
+            Err(DeltaError::NoKeyframe)
+        );
+
+        encoder.request_keyframe();
+        assert!(decoder.decode(encoder.encode(&frames[4])).is_ok());
+    }
+}
@@ -0,0 +1,565 @@
+// A minimal DNS-SD over multicast DNS implementation, just enough for servers to answer queries for their service
+// and for clients to browse for it. Only IPv4 is supported.
+//
+// Discovery is only used to find out where servers are, whether to trust them is still up to TLS.
+use crate::version::Version;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::io::Error;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::{self, Instant};
+
+pub const SERVICE: &str = "_rkvm._tcp.local";
+
+const GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const PORT: u16 = 5353;
+
+// How long to wait for servers to answer a query.
+const BROWSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Legacy unicast responses are supposed to have short TTLs, in seconds.
+const TTL: u32 = 10;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+
+const CLASS_IN: u16 = 1;
+// Set in the class of records only the sender has.
+const CACHE_FLUSH: u16 = 0x8000;
+
+// A server found by browsing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Service {
+    // The instance name, the hostname of the server.
+    pub name: String,
+    // The name the server is advertised under, for example "desktop.local". This is what its certificate is
+    // expected to be for.
+    pub host: String,
+    pub address: SocketAddr,
+    // The protocol version the server speaks, if it said.
+    pub version: Option<u16>,
+}
+
+impl Service {
+    // Servers that didn't say are assumed to be compatible, the handshake will tell.
+    pub fn is_compatible(&self) -> bool {
+        !matches!(self.version, Some(version) if version != Version::CURRENT.0)
+    }
+}
+
+// Answers queries for the service until an error occurs. The server is advertised under its hostname with
+// ".local" appended.
+pub async fn advertise(port: u16) -> Result<(), Error> {
+    let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")?;
+    let hostname = hostname.trim();
+
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    // Shared with other responders on the machine, such as Avahi.
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, PORT)).into())?;
+    socket.join_multicast_v4(&GROUP, &Ipv4Addr::UNSPECIFIED)?;
+
+    let socket = UdpSocket::from_std(socket.into())?;
+    let mut buffer = [0; 9000];
+
+    tracing::info!(name = %hostname, "Advertising the server");
+
+    loop {
+        let (length, source) = socket.recv_from(&mut buffer).await?;
+
+        let message = match Packet::parse(&buffer[..length]) {
+            Some(message) if !message.response => message,
+            _ => continue,
+        };
+
+        let wanted = message
+            .questions
+            .iter()
+            .any(|(name, kind)| name == SERVICE && (*kind == TYPE_PTR || *kind == TYPE_ANY));
+
+        if !wanted {
+            continue;
+        }
+
+        // The address the querier reaches this machine at, whichever interface that is on.
+        let address = match local_address(source) {
+            Ok(IpAddr::V4(address)) => address,
+            _ => continue,
+        };
+
+        tracing::debug!(source = %source, "Answering a query");
+
+        let response = response(message.id, hostname, address, port, &message.questions);
+
+        // Queries from port 5353 come from full mDNS implementations, which expect responses sent to the group.
+        // Anything else is a one-shot query wanting a direct response.
+        let destination = match source.port() {
+            PORT => SocketAddr::from((GROUP, PORT)),
+            _ => source,
+        };
+
+        socket.send_to(&response, destination).await?;
+    }
+}
+
+// Returns the servers that answered, sorted by name.
+pub async fn browse() -> Result<Vec<Service>, Error> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.set_multicast_ttl_v4(255)?;
+
+    let id = rand::random();
+    socket.send_to(&query(id), (GROUP, PORT)).await?;
+
+    let mut services = HashMap::new();
+    let mut buffer = [0; 9000];
+    let deadline = Instant::now() + BROWSE_TIMEOUT;
+
+    loop {
+        let (length, source) = match time::timeout_at(deadline, socket.recv_from(&mut buffer)).await
+        {
+            Ok(result) => result?,
+            Err(_) => break,
+        };
+
+        let message = match Packet::parse(&buffer[..length]) {
+            Some(message) if message.response && message.id == id => message,
+            _ => continue,
+        };
+
+        for service in message.services(source.ip()) {
+            services.insert(service.name.clone(), service);
+        }
+    }
+
+    let mut services = services.into_values().collect::<Vec<_>>();
+    services.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(services)
+}
+
+fn local_address(peer: SocketAddr) -> Result<IpAddr, Error> {
+    let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect(peer)?;
+
+    Ok(socket.local_addr()?.ip())
+}
+
+fn query(id: u16) -> Vec<u8> {
+    let mut data = Vec::new();
+
+    header(&mut data, id, false, [1, 0, 0, 0]);
+    name(&mut data, SERVICE);
+    data.extend_from_slice(&TYPE_PTR.to_be_bytes());
+    data.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    data
+}
+
+fn response(
+    id: u16,
+    hostname: &str,
+    address: Ipv4Addr,
+    port: u16,
+    questions: &[(String, u16)],
+) -> Vec<u8> {
+    let instance = format!("{}.{}", hostname, SERVICE);
+    let host = format!("{}.local", hostname);
+
+    let mut data = Vec::new();
+    header(&mut data, id, true, [questions.len() as u16, 1, 0, 3]);
+
+    // Echoed back, as one-shot queriers expect.
+    for (question, kind) in questions {
+        name(&mut data, question);
+        data.extend_from_slice(&kind.to_be_bytes());
+        data.extend_from_slice(&CLASS_IN.to_be_bytes());
+    }
+
+    record(&mut data, SERVICE, TYPE_PTR, CLASS_IN, |data| {
+        name(data, &instance)
+    });
+
+    record(
+        &mut data,
+        &instance,
+        TYPE_SRV,
+        CLASS_IN | CACHE_FLUSH,
+        |data| {
+            // Priority and weight.
+            data.extend_from_slice(&[0, 0, 0, 0]);
+            data.extend_from_slice(&port.to_be_bytes());
+            name(data, &host);
+        },
+    );
+
+    record(
+        &mut data,
+        &instance,
+        TYPE_TXT,
+        CLASS_IN | CACHE_FLUSH,
+        |data| {
+            let version = format!("version={}", Version::CURRENT.0);
+            data.push(version.len() as u8);
+            data.extend_from_slice(version.as_bytes());
+        },
+    );
+
+    record(&mut data, &host, TYPE_A, CLASS_IN | CACHE_FLUSH, |data| {
+        data.extend_from_slice(&address.octets())
+    });
+
+    data
+}
+
+fn header(data: &mut Vec<u8>, id: u16, response: bool, counts: [u16; 4]) {
+    data.extend_from_slice(&id.to_be_bytes());
+
+    // Authoritative answer.
+    let flags: u16 = match response {
+        true => 0x8400,
+        false => 0,
+    };
+    data.extend_from_slice(&flags.to_be_bytes());
+
+    for count in counts {
+        data.extend_from_slice(&count.to_be_bytes());
+    }
+}
+
+fn name(data: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        // Hostnames are limited to 63 characters per label anyway.
+        let label = &label.as_bytes()[..label.len().min(63)];
+
+        data.push(label.len() as u8);
+        data.extend_from_slice(label);
+    }
+
+    data.push(0);
+}
+
+fn record(
+    data: &mut Vec<u8>,
+    owner: &str,
+    kind: u16,
+    class: u16,
+    rdata: impl FnOnce(&mut Vec<u8>),
+) {
+    name(data, owner);
+    data.extend_from_slice(&kind.to_be_bytes());
+    data.extend_from_slice(&class.to_be_bytes());
+    data.extend_from_slice(&TTL.to_be_bytes());
+
+    // Filled in once known.
+    let length = data.len();
+    data.extend_from_slice(&[0, 0]);
+
+    rdata(data);
+
+    let rdlength = (data.len() - length - 2) as u16;
+    data[length..length + 2].copy_from_slice(&rdlength.to_be_bytes());
+}
+
+#[derive(Debug, PartialEq)]
+enum Data {
+    Ptr(String),
+    Srv { port: u16, target: String },
+    Txt(Vec<String>),
+    A(Ipv4Addr),
+    Other,
+}
+
+#[derive(Debug)]
+struct Record {
+    name: String,
+    data: Data,
+}
+
+#[derive(Debug)]
+struct Packet {
+    id: u16,
+    response: bool,
+    questions: Vec<(String, u16)>,
+    // Answers, authority and additional records alike.
+    records: Vec<Record>,
+}
+
+impl Packet {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let mut reader = Reader { data, offset: 0 };
+
+        let id = reader.u16()?;
+        let flags = reader.u16()?;
+        let counts = [reader.u16()?, reader.u16()?, reader.u16()?, reader.u16()?];
+
+        let mut questions = Vec::new();
+        for _ in 0..counts[0] {
+            let name = reader.name()?;
+            let kind = reader.u16()?;
+            let _class = reader.u16()?;
+
+            questions.push((name, kind));
+        }
+
+        let mut records = Vec::new();
+        for _ in 0..counts[1..]
+            .iter()
+            .map(|count| usize::from(*count))
+            .sum::<usize>()
+        {
+            let name = reader.name()?;
+            let kind = reader.u16()?;
+            let _class = reader.u16()?;
+            let _ttl = reader.u32()?;
+            let length = usize::from(reader.u16()?);
+
+            let end = reader.offset.checked_add(length)?;
+            if end > data.len() {
+                return None;
+            }
+
+            let data = match kind {
+                TYPE_PTR => Data::Ptr(reader.name()?),
+                TYPE_SRV => {
+                    let _priority = reader.u16()?;
+                    let _weight = reader.u16()?;
+                    let port = reader.u16()?;
+                    let target = reader.name()?;
+
+                    Data::Srv { port, target }
+                }
+                TYPE_TXT => {
+                    let mut strings = Vec::new();
+                    while reader.offset < end {
+                        let length = usize::from(reader.u8()?);
+                        let string = reader.bytes(length)?;
+
+                        strings.push(String::from_utf8_lossy(string).into_owned());
+                    }
+
+                    Data::Txt(strings)
+                }
+                TYPE_A if length == 4 => {
+                    let octets = reader.bytes(4)?;
+                    Data::A(Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]))
+                }
+                _ => Data::Other,
+            };
+
+            reader.offset = end;
+            records.push(Record { name, data });
+        }
+
+        Some(Self {
+            id,
+            response: flags & 0x8000 != 0,
+            questions,
+            records,
+        })
+    }
+
+    // The services described by the records, `source` being where the packet came from.
+    fn services(&self, source: IpAddr) -> Vec<Service> {
+        self.records
+            .iter()
+            .filter_map(|record| match &record.data {
+                Data::Ptr(instance) if record.name == SERVICE => Some(instance),
+                _ => None,
+            })
+            .filter_map(|instance| {
+                let (port, host) = self.records.iter().find_map(|record| match &record.data {
+                    Data::Srv { port, target } if record.name == *instance => Some((*port, target)),
+                    _ => None,
+                })?;
+
+                // Otherwise assume the server is wherever the response came from.
+                let address = self
+                    .records
+                    .iter()
+                    .find_map(|record| match record.data {
+                        Data::A(address) if record.name == *host => Some(IpAddr::V4(address)),
+                        _ => None,
+                    })
+                    .unwrap_or(source);
+
+                let version = self
+                    .records
+                    .iter()
+                    .filter(|record| record.name == *instance)
+                    .find_map(|record| match &record.data {
+                        Data::Txt(strings) => strings
+                            .iter()
+                            .find_map(|string| string.strip_prefix("version="))
+                            .and_then(|version| version.parse().ok()),
+                        _ => None,
+                    });
+
+                let name = instance
+                    .strip_suffix(&format!(".{}", SERVICE))
+                    .unwrap_or(instance);
+
+                Some(Service {
+                    name: name.to_owned(),
+                    host: host.clone(),
+                    address: SocketAddr::new(address, port),
+                    version,
+                })
+            })
+            .collect()
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> Option<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset.checked_add(length)?)?;
+        self.offset += length;
+
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|bytes| bytes[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4)
+            .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // Names are compared case insensitively, so they are returned in lowercase, without the trailing dot.
+    fn name(&mut self) -> Option<String> {
+        let mut labels = Vec::new();
+        let mut offset = self.offset;
+        // Where reading continues after the name, past the first compression pointer if any.
+        let mut end = None;
+
+        // Bounds the number of pointers followed, a malicious packet could otherwise make them loop.
+        for _ in 0..128 {
+            let length = *self.data.get(offset)?;
+
+            match length {
+                0 => {
+                    self.offset = end.unwrap_or(offset + 1);
+                    return Some(labels.join("."));
+                }
+                length if length & 0xc0 == 0xc0 => {
+                    let low = *self.data.get(offset + 1)?;
+                    end.get_or_insert(offset + 2);
+                    offset = usize::from(u16::from_be_bytes([length & 0x3f, low]));
+                }
+                length => {
+                    let label = self
+                        .data
+                        .get(offset + 1..offset + 1 + usize::from(length))?;
+                    labels.push(String::from_utf8_lossy(label).to_lowercase());
+                    offset += 1 + usize::from(length);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn query_round_trip() {
+        let packet = Packet::parse(&query(1234)).unwrap();
+
+        assert_eq!(packet.id, 1234);
+        assert!(!packet.response);
+        assert_eq!(packet.questions, [(SERVICE.to_owned(), TYPE_PTR)]);
+        assert!(packet.records.is_empty());
+    }
+
+    #[test]
+    fn response_round_trip() {
+        let questions = [(SERVICE.to_owned(), TYPE_PTR)];
+        let data = response(
+            1234,
+            "Desktop",
+            Ipv4Addr::new(10, 0, 0, 1),
+            5258,
+            &questions,
+        );
+
+        let packet = Packet::parse(&data).unwrap();
+        assert_eq!(packet.id, 1234);
+        assert!(packet.response);
+
+        assert_eq!(
+            packet.services(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))),
+            [Service {
+                name: "desktop".to_owned(),
+                host: "desktop.local".to_owned(),
+                address: "10.0.0.1:5258".parse().unwrap(),
+                version: Some(Version::CURRENT.0),
+            }]
+        );
+    }
+
+    #[test]
+    fn compression() {
+        let mut data = Vec::new();
+        header(&mut data, 0, true, [0, 2, 0, 0]);
+
+        // The service name, then "desktop" followed by a pointer to it.
+        let service = data.len() as u8;
+        record(&mut data, SERVICE, TYPE_PTR, CLASS_IN, |data| {
+            data.push(7);
+            data.extend_from_slice(b"desktop");
+            data.extend_from_slice(&[0xc0, service]);
+        });
+        record(
+            &mut data,
+            "desktop._rkvm._tcp.local",
+            TYPE_SRV,
+            CLASS_IN,
+            |data| {
+                data.extend_from_slice(&[0, 0, 0, 0, 0x14, 0x8a]);
+                data.extend_from_slice(&[0xc0, service]);
+            },
+        );
+
+        let services = Packet::parse(&data)
+            .unwrap()
+            .services(IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        assert_eq!(services.len(), 1);
+        assert_eq!(services[0].name, "desktop");
+        assert_eq!(services[0].host, SERVICE);
+        assert_eq!(services[0].address, "127.0.0.1:5258".parse().unwrap());
+        assert_eq!(services[0].version, None);
+    }
+
+    #[test]
+    fn pointer_loop() {
+        let mut data = Vec::new();
+        header(&mut data, 0, false, [1, 0, 0, 0]);
+        data.extend_from_slice(&[0xc0, 12, 0, 12, 0, 1]);
+
+        assert!(Packet::parse(&data).is_none());
+    }
+}
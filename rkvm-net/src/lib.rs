@@ -1,21 +1,20 @@
 pub mod auth;
+pub mod delta;
+#[cfg(feature = "discovery")]
+pub mod discovery;
 pub mod message;
 pub mod version;
 
-use rkvm_input::abs::{AbsAxis, AbsInfo};
+use rkvm_input::capabilities::DeviceCapabilities;
 use rkvm_input::event::Event;
-use rkvm_input::key::Key;
-use rkvm_input::rel::RelAxis;
+use rkvm_input::ff::FfRequest;
+use rkvm_input::led::LedEvent;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
-use std::ffi::CString;
 use std::future::Future;
 use std::io::{Error, ErrorKind};
 use std::time::Duration;
 use tokio::time;
 
-pub const PING_INTERVAL: Duration = Duration::from_secs(1);
-
 // Message read timeout (does not apply to updates, only auth negotiation and replies).
 pub const READ_TIMEOUT: Duration = Duration::from_millis(500);
 
@@ -25,17 +24,13 @@ pub const WRITE_TIMEOUT: Duration = Duration::from_millis(500);
 // TLS negotiation timeout.
 pub const TLS_TIMEOUT: Duration = Duration::from_millis(500);
 
+// Device creation is rare, so boxing it to shrink the other variants isn't worth it.
+#[allow(clippy::large_enum_variant)]
 #[derive(Deserialize, Serialize, Debug)]
 pub enum Update {
     CreateDevice {
         id: usize,
-        name: CString,
-        vendor: u16,
-        product: u16,
-        version: u16,
-        rel: HashSet<RelAxis>,
-        abs: HashMap<AbsAxis, AbsInfo>,
-        keys: HashSet<Key>,
+        capabilities: DeviceCapabilities,
     },
     DestroyDevice {
         id: usize,
@@ -44,11 +39,28 @@ pub enum Update {
         id: usize,
         event: Event,
     },
-    Ping,
+    // The events of a device up to and including a SYN_REPORT, sent as one message rather than one each.
+    Frame {
+        id: usize,
+        events: Vec<Event>,
+    },
+    // How long the client should wait for the next ping before giving up on the server.
+    Ping {
+        timeout: Duration,
+    },
 }
 
+// Sent by the client, the only message in that direction once authenticated.
 #[derive(Deserialize, Serialize, Debug)]
-pub struct Pong;
+pub enum Feedback {
+    // The reply to Update::Ping.
+    Pong,
+    // Userspace on the client changed an LED of one of the devices.
+    Led { id: usize, event: LedEvent },
+    // Userspace on the client made a force feedback request to one of the devices, with the effect IDs
+    // allocated by the client.
+    Ff { id: usize, request: FfRequest },
+}
 
 pub async fn timeout<T: Future<Output = Result<U, Error>>, U>(
     duration: Duration,
@@ -63,12 +75,122 @@ pub async fn timeout<T: Future<Output = Result<U, Error>>, U>(
 mod test {
     use super::message::Message;
     use super::*;
+    use rkvm_input::abs::{AbsAxis, AbsEvent, ToolType};
+    use rkvm_input::key::{Button, Key, KeyEvent, Keyboard};
+    use rkvm_input::led::Led;
+    use rkvm_input::msc::{Msc, MscEvent};
+    use rkvm_input::rel::{RelAxis, RelEvent};
+    use rkvm_input::rep::{Rep, RepEvent};
+    use rkvm_input::switch::{Switch, SwitchEvent};
+    use rkvm_input::sync::SyncEvent;
 
     #[tokio::test]
     async fn pong_is_not_empty() {
         let mut data = Vec::new();
-        Pong.encode(&mut data).await.unwrap();
+        Feedback::Pong.encode(&mut data).await.unwrap();
 
         assert!(!data.is_empty());
     }
+
+    #[tokio::test]
+    async fn events_round_trip() {
+        let events = [
+            Event::Rel(RelEvent {
+                axis: RelAxis::WheelHiRes,
+                value: -120,
+            }),
+            Event::Abs(AbsEvent::Axis {
+                axis: AbsAxis::MtPositionX,
+                value: 1000,
+            }),
+            Event::Abs(AbsEvent::MtToolType {
+                value: ToolType::Pen,
+            }),
+            Event::Key(KeyEvent {
+                key: Key::Key(Keyboard::A),
+                down: true,
+            }),
+            Event::Key(KeyEvent {
+                key: Key::Button(Button::Left),
+                down: false,
+            }),
+            Event::Key(KeyEvent {
+                key: Key::Other(0x2ff),
+                down: true,
+            }),
+            Event::Led(LedEvent {
+                led: Led::CapsLock,
+                state: true,
+            }),
+            Event::Msc(MscEvent {
+                code: Msc::Scan,
+                value: 0x70004,
+            }),
+            Event::Rep(RepEvent {
+                code: Rep::Delay,
+                value: 250,
+            }),
+            Event::Switch(SwitchEvent {
+                switch: Switch::TabletMode,
+                value: true,
+            }),
+            Event::Switch(SwitchEvent {
+                switch: Switch::Other(0x10),
+                value: false,
+            }),
+            Event::Sync(SyncEvent::Mt),
+            Event::Sync(SyncEvent::All),
+        ];
+
+        for event in events {
+            let mut data = Vec::new();
+            event.encode(&mut data).await.unwrap();
+
+            assert_eq!(Event::decode(&mut data.as_slice()).await.unwrap(), event);
+        }
+    }
+
+    // Reordering the variants changes what every event means on the wire.
+    #[tokio::test]
+    async fn event_variants() {
+        let events = [
+            Event::Rel(RelEvent {
+                axis: RelAxis::X,
+                value: 1,
+            }),
+            Event::Abs(AbsEvent::Axis {
+                axis: AbsAxis::X,
+                value: 1,
+            }),
+            Event::Key(KeyEvent {
+                key: Key::Key(Keyboard::A),
+                down: true,
+            }),
+            Event::Msc(MscEvent {
+                code: Msc::Scan,
+                value: 1,
+            }),
+            Event::Rep(RepEvent {
+                code: Rep::Delay,
+                value: 1,
+            }),
+            Event::Sync(SyncEvent::All),
+            Event::Switch(SwitchEvent {
+                switch: Switch::Lid,
+                value: true,
+            }),
+            Event::Led(LedEvent {
+                led: Led::NumLock,
+                state: true,
+            }),
+        ];
+
+        for (index, event) in events.into_iter().enumerate() {
+            let mut data = Vec::new();
+            event.encode(&mut data).await.unwrap();
+
+            // After the length.
+            assert_eq!(data[2], index as u8, "{:?}", event);
+        }
+    }
 }
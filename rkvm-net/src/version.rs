@@ -1,14 +1,19 @@
 use crate::message::Message;
 
 use std::fmt::{self, Display, Formatter};
-use std::io::Error;
+use std::io::{Error, ErrorKind};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 #[derive(Clone, Copy, Debug, PartialEq)]
-pub struct Version(u16);
+pub struct Version(pub(crate) u16);
+
+// Sent ahead of the version, so that anything other than rkvm on the other end is told apart from an
+// incompatible version of it.
+const MAGIC: [u8; 4] = *b"rkvm";
 
 impl Version {
-    pub const CURRENT: Self = Self(4);
+    // Bumped whenever the encoding of any message changes.
+    pub const CURRENT: Self = Self(16);
 }
 
 impl Display for Version {
@@ -20,10 +25,58 @@ impl Display for Version {
 #[async_trait::async_trait]
 impl Message for Version {
     async fn decode<R: AsyncRead + Send + Unpin>(stream: &mut R) -> Result<Self, Error> {
+        let mut magic = [0; MAGIC.len()];
+        stream.read_exact(&mut magic).await?;
+
+        if magic != MAGIC {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Peer does not speak the rkvm protocol, or a version older than v13",
+            ));
+        }
+
         stream.read_u16_le().await.map(Self)
     }
 
     async fn encode<W: AsyncWrite + Send + Unpin>(&self, stream: &mut W) -> Result<(), Error> {
+        stream.write_all(&MAGIC).await?;
         stream.write_u16_le(self.0).await
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn round_trip() {
+        let mut data = Vec::new();
+        Version::CURRENT.encode(&mut data).await.unwrap();
+
+        assert_eq!(
+            Version::decode(&mut data.as_slice()).await.unwrap(),
+            Version::CURRENT
+        );
+    }
+
+    #[tokio::test]
+    async fn mismatch() {
+        // Versions before the magic was introduced only sent the number.
+        let err = Version::decode(&mut [12, 0, 0, 0, 0, 0].as_slice())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            "Peer does not speak the rkvm protocol, or a version older than v13"
+        );
+
+        // The number has to be compared by the caller, which reports it along with its own.
+        let version = Version::decode(&mut b"rkvm\x02\x00".as_slice())
+            .await
+            .unwrap();
+        assert_eq!(version, Version(2));
+        assert_ne!(version, Version::CURRENT);
+        assert_eq!(version.to_string(), "v2");
+    }
+}
@@ -0,0 +1,202 @@
+use rkvm_input::key::{Key, KeyEvent};
+use std::collections::HashSet;
+
+// Tracks the switch keys so that none of them leak to a target when switching. Their presses are held back until
+// either the chord is complete, in which case neither the presses nor the releases reach anyone, or it turns out
+// not to be a switch after all, in which case they are flushed to the current target. Switch keys that made it
+// to a target are released there when switching away from it.
+//
+// Keys are paired with the ID of the device they come from.
+pub struct Chord {
+    keys: HashSet<Key>,
+    // Switch keys physically held.
+    pressed: HashSet<Key>,
+    // In the order they were pressed.
+    withheld: Vec<(usize, Key)>,
+    // Held switch keys the current target knows about.
+    forwarded: HashSet<(usize, Key)>,
+    // Held switch keys whose release nobody is supposed to see.
+    swallowed: HashSet<(usize, Key)>,
+}
+
+impl Chord {
+    pub fn new(keys: HashSet<Key>) -> Self {
+        Self {
+            keys,
+            pressed: HashSet::new(),
+            withheld: Vec::new(),
+            forwarded: HashSet::new(),
+            swallowed: HashSet::new(),
+        }
+    }
+
+    pub fn keys(&self) -> &HashSet<Key> {
+        &self.keys
+    }
+
+    // Whether all of the switch keys are held.
+    pub fn is_complete(&self) -> bool {
+        self.pressed.len() == self.keys.len()
+    }
+
+    // Whether no switch key is held or waiting for its release to be dealt with.
+    pub fn is_idle(&self) -> bool {
+        self.pressed.is_empty()
+            && self.withheld.is_empty()
+            && self.forwarded.is_empty()
+            && self.swallowed.is_empty()
+    }
+
+    // Returns the key events to send to the current target.
+    pub fn key(&mut self, id: usize, key: Key, down: bool) -> Vec<(usize, KeyEvent)> {
+        if !self.keys.contains(&key) {
+            // Pressing anything else turns the chord into a shortcut, Ctrl+Alt+T should still work with
+            // Ctrl+Alt+Shift as the switch keys. Releases are fine, they could be what remains of typing.
+            let mut events = match down {
+                true => self.flush(),
+                false => Vec::new(),
+            };
+
+            events.push((id, KeyEvent { key, down }));
+            return events;
+        }
+
+        if down {
+            self.pressed.insert(key);
+            self.withheld.push((id, key));
+
+            return Vec::new();
+        }
+
+        self.pressed.remove(&key);
+
+        if self.swallowed.remove(&(id, key)) {
+            return Vec::new();
+        }
+
+        // Changed your mind, the chord so far was meant for the current target.
+        let mut events = match self.withheld.contains(&(id, key)) {
+            true => self.flush(),
+            false => Vec::new(),
+        };
+
+        self.forwarded.remove(&(id, key));
+        events.push((id, KeyEvent { key, down }));
+
+        events
+    }
+
+    // Sends the held back presses to the current target, returning the events to do so.
+    pub fn flush(&mut self) -> Vec<(usize, KeyEvent)> {
+        self.withheld
+            .drain(..)
+            .map(|(id, key)| {
+                self.forwarded.insert((id, key));
+                (id, KeyEvent { key, down: true })
+            })
+            .collect()
+    }
+
+    // The chord did its job, none of the held keys are going anywhere.
+    pub fn complete(&mut self) {
+        self.swallowed.extend(self.withheld.drain(..));
+    }
+
+    // Switching away from the current target, returns the switch keys to release there.
+    pub fn switched(&mut self) -> Vec<(usize, Key)> {
+        let keys = self.forwarded.drain().collect::<Vec<_>>();
+        self.swallowed.extend(keys.iter().copied());
+
+        keys
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rkvm_input::key::Keyboard;
+
+    const CTRL: Key = Key::Key(Keyboard::LeftCtrl);
+    const ALT: Key = Key::Key(Keyboard::LeftAlt);
+    const T: Key = Key::Key(Keyboard::T);
+
+    fn chord() -> Chord {
+        Chord::new([CTRL, ALT].into_iter().collect())
+    }
+
+    fn event(key: Key, down: bool) -> (usize, KeyEvent) {
+        (0, KeyEvent { key, down })
+    }
+
+    #[test]
+    fn switch() {
+        let mut chord = chord();
+
+        assert_eq!(chord.key(0, CTRL, true), []);
+        assert_eq!(chord.key(0, ALT, true), []);
+        assert!(chord.is_complete());
+
+        chord.complete();
+        assert_eq!(chord.switched(), []);
+        assert!(!chord.is_idle());
+
+        assert_eq!(chord.key(0, ALT, false), []);
+        assert_eq!(chord.key(0, CTRL, false), []);
+        assert!(!chord.is_complete());
+        assert!(chord.is_idle());
+
+        assert_eq!(chord.key(0, T, true), [event(T, true)]);
+    }
+
+    #[test]
+    fn changed_mind() {
+        let mut chord = chord();
+
+        assert_eq!(chord.key(0, CTRL, true), []);
+        assert_eq!(
+            chord.key(0, CTRL, false),
+            [event(CTRL, true), event(CTRL, false)]
+        );
+
+        assert_eq!(chord.switched(), []);
+    }
+
+    #[test]
+    fn shortcut() {
+        let mut chord = chord();
+
+        assert_eq!(chord.key(0, CTRL, true), []);
+        assert_eq!(chord.key(0, T, true), [event(CTRL, true), event(T, true)]);
+        assert_eq!(chord.key(0, T, false), [event(T, false)]);
+
+        // Completing the chord now releases the part the target knows about.
+        assert_eq!(chord.key(0, ALT, true), []);
+        assert!(chord.is_complete());
+
+        chord.complete();
+        assert_eq!(chord.switched(), [(0, CTRL)]);
+
+        assert_eq!(chord.key(0, CTRL, false), []);
+        assert_eq!(chord.key(0, ALT, false), []);
+    }
+
+    #[test]
+    fn overlapping() {
+        let mut chord = chord();
+
+        assert_eq!(chord.key(0, CTRL, true), []);
+        assert_eq!(chord.key(0, ALT, true), []);
+        chord.complete();
+        chord.switched();
+
+        // Alt tapped again with Ctrl still held switches again.
+        assert_eq!(chord.key(0, ALT, false), []);
+        assert_eq!(chord.key(0, ALT, true), []);
+        assert!(chord.is_complete());
+        chord.complete();
+        assert_eq!(chord.switched(), []);
+
+        assert_eq!(chord.key(0, CTRL, false), []);
+        assert_eq!(chord.key(0, ALT, false), []);
+    }
+}
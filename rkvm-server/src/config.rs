@@ -1,7 +1,9 @@
+use crate::layout::Edge;
 use rkvm_input::key::{Button, Key, Keyboard};
 use serde::Deserialize;
-use std::collections::HashSet;
-use std::net::SocketAddr;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::num::{NonZeroU32, NonZeroU64};
 use std::path::PathBuf;
 
 #[derive(Deserialize)]
@@ -13,6 +15,116 @@ pub struct Config {
     pub password: String,
     pub switch_keys: HashSet<SwitchKey>,
     pub input_device_paths: HashSet<String>,
+    // Where to listen for control connections, see `control::Request`.
+    pub control_socket: Option<PathBuf>,
+    // Name globs or vendor:product IDs, see `MatchSpec::parse`.
+    #[serde(default)]
+    pub devices: Vec<String>,
+    // In seconds, how often to try grabbing devices some other process has grabbed, zero to skip them instead.
+    #[serde(default = "default_grab_retry_interval")]
+    pub grab_retry_interval: u64,
+    #[serde(default)]
+    pub fail_on_grabbed: bool,
+    #[serde(default)]
+    pub repress_keys_on_switch: bool,
+    // Release the devices while the server itself is the target.
+    #[serde(default)]
+    pub passthrough_local: bool,
+    // Switching directly to a client or back to the server, see `Client`.
+    #[serde(default)]
+    pub clients: Vec<Client>,
+    #[serde(default)]
+    pub local_key: Option<SwitchKey>,
+    // Pins input to the current target until pressed again.
+    #[serde(default)]
+    pub lock_key: Option<SwitchKey>,
+    // Of the screen of the server, switching by moving the cursor past the edges of the screens is only enabled
+    // if set and some client has a position.
+    pub resolution: Option<[NonZeroU32; 2]>,
+    // In milliseconds.
+    #[serde(default = "default_ping_interval")]
+    pub ping_interval: NonZeroU64,
+    #[serde(default = "default_missed_pongs")]
+    pub missed_pongs: NonZeroU32,
+    // In microseconds.
+    #[serde(default)]
+    pub max_frame_delay_us: u64,
+    // Answer mDNS queries from clients looking for a server, requires the discovery feature.
+    #[serde(default)]
+    pub advertise: bool,
+}
+
+// Pressing the key along with the switch keys switches to the client connecting from the address.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Client {
+    pub address: IpAddr,
+    pub key: Option<SwitchKey>,
+    pub resolution: Option<[NonZeroU32; 2]>,
+    pub position: Option<Position>,
+    // Keys replaced by others when sent to this client, for example { left-meta = "left-ctrl" }.
+    #[serde(default)]
+    pub remap: HashMap<SwitchKey, SwitchKey>,
+    // Width and height to rescale the position axes of absolute pointing devices to.
+    pub scale_abs_to: Option<[u32; 2]>,
+}
+
+// Which edge of which screen a client's screen is next to, for example "right-of server" or "below 10.0.0.2".
+#[derive(Deserialize, Clone, Copy)]
+#[serde(try_from = "String")]
+pub struct Position {
+    pub edge: Edge,
+    // None being the server.
+    pub of: Option<IpAddr>,
+}
+
+impl TryFrom<String> for Position {
+    type Error = String;
+
+    fn try_from(data: String) -> Result<Self, Self::Error> {
+        let (edge, of) = data.split_once(' ').ok_or_else(|| {
+            format!(
+                "Invalid position {:?}, expected for example \"right-of server\"",
+                data
+            )
+        })?;
+
+        let edge = match edge {
+            "left-of" => Edge::Left,
+            "right-of" => Edge::Right,
+            "above" => Edge::Top,
+            "below" => Edge::Bottom,
+            _ => {
+                return Err(format!(
+                    "Invalid side {:?}, expected left-of, right-of, above or below",
+                    edge
+                ))
+            }
+        };
+
+        let of = match of.trim() {
+            "server" => None,
+            address => Some(
+                address
+                    .parse()
+                    .map_err(|err| format!("Invalid address {:?}: {}", address, err))?,
+            ),
+        };
+
+        Ok(Self { edge, of })
+    }
+}
+
+fn default_ping_interval() -> NonZeroU64 {
+    NonZeroU64::new(1000).unwrap()
+}
+
+fn default_grab_retry_interval() -> u64 {
+    5
+}
+
+fn default_missed_pongs() -> NonZeroU32 {
+    NonZeroU32::new(3).unwrap()
 }
 
 #[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
@@ -0,0 +1,188 @@
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use tokio::fs;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+
+// The protocol is line based. Every request is a single line, every response is "ok" followed by the lines of
+// the reply or "error <message>", and is terminated by an empty line. Fields within the lines of a reply are
+// separated by tabs. Machines are named by their address, the server being "server".
+#[derive(Debug, PartialEq, Eq)]
+pub enum Request {
+    // Every known machine, with whether it is connected and whether it is the active one.
+    List,
+    // The active machine and whether input is locked to it.
+    Status,
+    Switch(String),
+    SwitchNext,
+}
+
+impl Request {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+
+        let request = match (words.next(), words.next()) {
+            (Some("list"), None) => Self::List,
+            (Some("status"), None) => Self::Status,
+            (Some("switch"), Some(name)) => Self::Switch(name.to_owned()),
+            (Some("switch-next"), None) => Self::SwitchNext,
+            _ => return Err(format!("Invalid request {:?}", line)),
+        };
+
+        if words.next().is_some() {
+            return Err(format!("Invalid request {:?}", line));
+        }
+
+        Ok(request)
+    }
+}
+
+pub type Reply = Result<Vec<String>, String>;
+
+pub type Requests = Sender<(Request, oneshot::Sender<Reply>)>;
+
+pub async fn bind(path: &Path) -> Result<UnixListener, io::Error> {
+    // Left behind by a previous run that didn't exit cleanly.
+    match fs::remove_file(path).await {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+
+    // Switching machines is as good as having the keyboard, so it's the same as for input devices. The socket is
+    // created that way rather than changed afterwards, so that nobody else can connect in between.
+    let mask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(mask) };
+
+    listener
+}
+
+pub async fn listen(listener: UnixListener, requests: Requests) -> Result<(), io::Error> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let requests = requests.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = serve(stream, requests).await {
+                tracing::debug!("Control connection error: {}", err);
+            }
+        });
+    }
+}
+
+async fn serve(stream: UnixStream, requests: Requests) -> Result<(), io::Error> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        let reply = match Request::parse(&line) {
+            Ok(request) => {
+                let (sender, receiver) = oneshot::channel();
+                if requests.send((request, sender)).await.is_err() {
+                    break;
+                }
+
+                receiver
+                    .await
+                    .unwrap_or_else(|_| Err("Server is shutting down".to_owned()))
+            }
+            Err(err) => Err(err),
+        };
+
+        writer.write_all(format(&reply).as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+fn format(reply: &Reply) -> String {
+    let mut data = match reply {
+        Ok(lines) => lines
+            .iter()
+            .fold("ok\n".to_owned(), |data, line| data + line + "\n"),
+        Err(err) => format!("error {}\n", err),
+    };
+
+    data.push('\n');
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::io::AsyncReadExt;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn parse() {
+        assert_eq!(Request::parse("list"), Ok(Request::List));
+        assert_eq!(
+            Request::parse(" switch  10.0.0.2 "),
+            Ok(Request::Switch("10.0.0.2".to_owned()))
+        );
+        assert_eq!(Request::parse("switch-next"), Ok(Request::SwitchNext));
+
+        assert!(Request::parse("switch").is_err());
+        assert!(Request::parse("status now").is_err());
+        assert!(Request::parse("").is_err());
+    }
+
+    #[test]
+    fn replies() {
+        assert_eq!(
+            format(&Ok(vec!["server\tconnected\tactive".to_owned()])),
+            "ok\nserver\tconnected\tactive\n\n"
+        );
+        assert_eq!(format(&Ok(Vec::new())), "ok\n\n");
+        assert_eq!(
+            format(&Err("Input is locked".to_owned())),
+            "error Input is locked\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn permissions() {
+        let path = std::env::temp_dir().join(format!("rkvm-control-{}.sock", std::process::id()));
+        let _listener = bind(&path).await.unwrap();
+
+        let mode = fs::metadata(&path).await.unwrap().permissions().mode();
+        fs::remove_file(&path).await.unwrap();
+
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[tokio::test]
+    async fn session() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let (sender, mut receiver) = mpsc::channel(1);
+
+        tokio::spawn(serve(server, sender));
+        tokio::spawn(async move {
+            while let Some((request, reply)) = receiver.recv().await {
+                let _ = reply.send(match request {
+                    Request::Status => Ok(vec!["active\tserver".to_owned()]),
+                    _ => Err("Input is locked".to_owned()),
+                });
+            }
+        });
+
+        let (mut reader, mut writer) = client.into_split();
+        writer
+            .write_all(b"status\nswitch-next\nbogus\n")
+            .await
+            .unwrap();
+        drop(writer);
+
+        let mut data = String::new();
+        reader.read_to_string(&mut data).await.unwrap();
+
+        assert_eq!(
+            data,
+            "ok\nactive\tserver\n\nerror Input is locked\n\nerror Invalid request \"bogus\"\n\n"
+        );
+    }
+}
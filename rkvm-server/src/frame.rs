@@ -0,0 +1,126 @@
+use rkvm_input::event::Event;
+use rkvm_input::rel::{RelAxis, RelEvent};
+use rkvm_input::sync::SyncEvent;
+
+// The most events sent in one message.
+pub const MAX_EVENTS: usize = 1024;
+
+// Sums consecutive pointer motion along the same axis, which is how userspace would end up seeing it anyway.
+pub fn coalesce(events: Vec<Event>) -> Vec<Event> {
+    let mut coalesced: Vec<Event> = Vec::with_capacity(events.len());
+
+    for event in events {
+        if let (Some(Event::Rel(last)), Event::Rel(rel)) = (coalesced.last_mut(), &event) {
+            if last.axis == rel.axis && is_motion_axis(rel.axis) {
+                last.value = last.value.saturating_add(rel.value);
+                continue;
+            }
+        }
+
+        coalesced.push(event);
+    }
+
+    coalesced
+}
+
+// Whether the frame is complete and nothing but the pointer moved in it, such frames can be merged without
+// anything getting out of order.
+pub fn is_motion(events: &[Event]) -> bool {
+    events.last() == Some(&Event::Sync(SyncEvent::All))
+        && events.iter().all(|event| match event {
+            Event::Rel(RelEvent { axis, .. }) => is_motion_axis(*axis),
+            Event::Sync(SyncEvent::All) => true,
+            _ => false,
+        })
+}
+
+// Adds the motion of a later frame to that of an earlier one, both expected to be motion frames.
+pub fn merge(into: &mut Vec<Event>, events: &[Event]) {
+    for event in events {
+        let rel = match event {
+            Event::Rel(rel) => rel,
+            _ => continue,
+        };
+
+        let existing = into.iter_mut().find_map(|event| match event {
+            Event::Rel(existing) if existing.axis == rel.axis => Some(existing),
+            _ => None,
+        });
+
+        match existing {
+            Some(existing) => existing.value = existing.value.saturating_add(rel.value),
+            // Before the SYN_REPORT.
+            None => into.insert(into.len().saturating_sub(1), Event::Rel(*rel)),
+        }
+    }
+}
+
+fn is_motion_axis(axis: RelAxis) -> bool {
+    matches!(axis, RelAxis::X | RelAxis::Y)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rkvm_input::key::{Button, Key, KeyEvent};
+
+    const SYNC: Event = Event::Sync(SyncEvent::All);
+
+    fn rel(axis: RelAxis, value: i32) -> Event {
+        Event::Rel(RelEvent { axis, value })
+    }
+
+    #[test]
+    fn coalescing() {
+        let events = vec![
+            rel(RelAxis::X, 3),
+            rel(RelAxis::X, -1),
+            rel(RelAxis::Y, 2),
+            rel(RelAxis::Wheel, 1),
+            rel(RelAxis::Wheel, 1),
+            rel(RelAxis::Y, i32::MAX),
+            rel(RelAxis::Y, 1),
+            SYNC,
+        ];
+
+        assert_eq!(
+            coalesce(events),
+            [
+                rel(RelAxis::X, 2),
+                rel(RelAxis::Y, 2),
+                rel(RelAxis::Wheel, 1),
+                rel(RelAxis::Wheel, 1),
+                rel(RelAxis::Y, i32::MAX),
+                SYNC,
+            ]
+        );
+    }
+
+    #[test]
+    fn motion() {
+        assert!(is_motion(&[rel(RelAxis::X, 1), rel(RelAxis::Y, 1), SYNC]));
+        assert!(!is_motion(&[rel(RelAxis::Wheel, 1), SYNC]));
+        assert!(!is_motion(&[rel(RelAxis::X, 1)]));
+        assert!(!is_motion(&[
+            rel(RelAxis::X, 1),
+            Event::Key(KeyEvent {
+                key: Key::Button(Button::Left),
+                down: true,
+            }),
+            SYNC,
+        ]));
+    }
+
+    #[test]
+    fn merging() {
+        let mut events = vec![rel(RelAxis::X, 1), SYNC];
+
+        merge(
+            &mut events,
+            &[rel(RelAxis::X, 2), rel(RelAxis::Y, -4), SYNC],
+        );
+        merge(&mut events, &[rel(RelAxis::Y, 1), SYNC]);
+
+        assert_eq!(events, [rel(RelAxis::X, 3), rel(RelAxis::Y, -3), SYNC]);
+    }
+}
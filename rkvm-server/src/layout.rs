@@ -0,0 +1,247 @@
+use crate::server::Target;
+use rkvm_input::rel::RelAxis;
+use std::collections::HashMap;
+
+// How far past an edge the cursor has to be pushed before switching.
+const THRESHOLD: i64 = 16;
+
+// Pushing against an edge this close to a corner never switches, so that close buttons and the like stay usable.
+const CORNER: i64 = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Edge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+impl Edge {
+    fn opposite(self) -> Self {
+        match self {
+            Self::Left => Self::Right,
+            Self::Right => Self::Left,
+            Self::Top => Self::Bottom,
+            Self::Bottom => Self::Top,
+        }
+    }
+}
+
+// Where to switch to after the cursor crossed an edge, along with the motion past the edge, meant for the target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crossing {
+    pub target: Target,
+    pub axis: RelAxis,
+    pub value: i32,
+}
+
+// Screens next to each other, with a virtual cursor following relative motion. The cursor is only an estimate,
+// pointer acceleration on the machines makes it drift, which is why it is put back in the middle of a screen
+// whenever it is switched to some other way than by crossing an edge.
+#[derive(Clone, Default)]
+pub struct Layout {
+    resolutions: HashMap<Target, [u32; 2]>,
+    neighbors: HashMap<(Target, Edge), Target>,
+    // The screen the cursor is on and where.
+    screen: Option<Target>,
+    position: [i64; 2],
+    // How far the cursor was pushed past the edge it is against.
+    push: i64,
+}
+
+impl Layout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A screen without any pixels has no edges to cross, it is treated like one without a resolution.
+    pub fn resolution(&mut self, target: Target, resolution: [u32; 2]) {
+        if resolution.contains(&0) {
+            self.resolutions.remove(&target);
+            return;
+        }
+
+        self.resolutions.insert(target, resolution);
+    }
+
+    // Puts `target` next to the given edge of `of`.
+    pub fn neighbor(&mut self, target: Target, edge: Edge, of: Target) {
+        self.neighbors.insert((of, edge), target);
+        self.neighbors.insert((target, edge.opposite()), of);
+    }
+
+    // Returns where to switch to if the motion took the cursor past an edge, the caller is expected to call
+    // `enter` if it actually does.
+    pub fn motion(&mut self, current: Target, axis: RelAxis, value: i32) -> Option<Crossing> {
+        if self.neighbors.is_empty() {
+            return None;
+        }
+
+        let [width, height] = (*self.resolutions.get(&current)?).map(i64::from);
+
+        if self.screen != Some(current) {
+            self.screen = Some(current);
+            self.position = [width / 2, height / 2];
+            self.push = 0;
+        }
+
+        let (index, size, edges) = match axis {
+            RelAxis::X => (0, width, [Edge::Left, Edge::Right]),
+            RelAxis::Y => (1, height, [Edge::Top, Edge::Bottom]),
+            _ => return None,
+        };
+
+        let position = self.position[index] + i64::from(value);
+        let (edge, past) = if position < 0 {
+            (edges[0], -position)
+        } else if position >= size {
+            (edges[1], position - (size - 1))
+        } else {
+            self.position[index] = position;
+            self.push = 0;
+
+            return None;
+        };
+
+        self.position[index] = position.clamp(0, size - 1);
+
+        let other = self.position[1 - index];
+        let other_size = [width, height][1 - index];
+        if other < CORNER || other >= other_size - CORNER {
+            self.push = 0;
+            return None;
+        }
+
+        let target = *self.neighbors.get(&(current, edge))?;
+        if !self.resolutions.contains_key(&target) {
+            return None;
+        }
+
+        self.push += past;
+        if self.push <= THRESHOLD {
+            return None;
+        }
+
+        let value = match edge {
+            Edge::Left | Edge::Top => -self.push,
+            Edge::Right | Edge::Bottom => self.push,
+        };
+
+        Some(Crossing {
+            target,
+            axis,
+            value: value.try_into().unwrap_or(i32::MAX),
+        })
+    }
+
+    // Moves the cursor onto the target of the crossing, at the edge it came from.
+    pub fn enter(&mut self, crossing: &Crossing) {
+        let (from, to) = match (self.screen, self.resolutions.get(&crossing.target)) {
+            (Some(from), Some(to)) => (self.resolutions[&from].map(i64::from), to.map(i64::from)),
+            _ => return,
+        };
+
+        let index = match crossing.axis {
+            RelAxis::X => 0,
+            _ => 1,
+        };
+
+        // Keep the relative position along the edge.
+        let other = 1 - index;
+        self.position[other] = self.position[other] * to[other] / from[other].max(1);
+
+        let past = i64::from(crossing.value).abs().min(to[index] - 1);
+        self.position[index] = match crossing.value < 0 {
+            true => to[index] - 1 - past,
+            false => past,
+        };
+
+        self.screen = Some(crossing.target);
+        self.push = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    const CLIENT: Target = Target::Client(IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+    fn layout() -> Layout {
+        let mut layout = Layout::new();
+        layout.resolution(Target::Local, [1000, 500]);
+        layout.resolution(CLIENT, [2000, 1000]);
+        layout.neighbor(CLIENT, Edge::Right, Target::Local);
+
+        layout
+    }
+
+    #[test]
+    fn crossing() {
+        let mut layout = layout();
+
+        // From the middle to right at the edge.
+        assert_eq!(layout.motion(Target::Local, RelAxis::X, 499), None);
+        // There is nothing to the left.
+        assert_eq!(layout.motion(Target::Local, RelAxis::X, -2000), None);
+        assert_eq!(layout.motion(Target::Local, RelAxis::X, 999), None);
+        assert_eq!(
+            layout.motion(Target::Local, RelAxis::X, THRESHOLD as _),
+            None
+        );
+
+        let crossing = layout.motion(Target::Local, RelAxis::X, 4).unwrap();
+        assert_eq!(
+            crossing,
+            Crossing {
+                target: CLIENT,
+                axis: RelAxis::X,
+                value: THRESHOLD as i32 + 4,
+            }
+        );
+
+        layout.enter(&crossing);
+        assert_eq!(layout.position, [THRESHOLD + 4, 500]);
+
+        // And back.
+        assert_eq!(
+            layout.motion(CLIENT, RelAxis::X, -(THRESHOLD as i32 + 4)),
+            None
+        );
+        let crossing = layout
+            .motion(CLIENT, RelAxis::X, -(THRESHOLD as i32 + 1))
+            .unwrap();
+        assert_eq!(crossing.target, Target::Local);
+        assert_eq!(crossing.value, -(THRESHOLD as i32 + 1));
+    }
+
+    #[test]
+    fn corners() {
+        let mut layout = layout();
+
+        assert_eq!(layout.motion(Target::Local, RelAxis::Y, -1000), None);
+        assert_eq!(layout.motion(Target::Local, RelAxis::X, 1000), None);
+        assert_eq!(layout.motion(Target::Local, RelAxis::X, 1000), None);
+    }
+
+    #[test]
+    fn zero_size() {
+        let mut layout = layout();
+        layout.resolution(Target::Local, [0, 500]);
+        layout.resolution(CLIENT, [2000, 0]);
+
+        assert_eq!(layout.motion(Target::Local, RelAxis::X, 2000), None);
+        assert_eq!(layout.motion(CLIENT, RelAxis::Y, -2000), None);
+    }
+
+    #[test]
+    fn reanchor() {
+        let mut layout = layout();
+
+        assert_eq!(layout.motion(Target::Local, RelAxis::X, 400), None);
+        // Switched some other way, the cursor starts in the middle.
+        assert_eq!(layout.motion(CLIENT, RelAxis::X, 0), None);
+        assert_eq!(layout.position, [1000, 500]);
+    }
+}
@@ -1,13 +1,25 @@
+mod chord;
 mod config;
+mod control;
+mod frame;
+mod layout;
+mod scale;
 mod server;
 mod tls;
 
 use clap::Parser;
 use config::Config;
+use layout::Layout;
+use rkvm_input::diagnostics::MatchSpec;
+use rkvm_input::monitor::GrabbedPolicy;
+use server::{Keepalive, Options, Settings, Target};
 use std::future;
-use std::path::PathBuf;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 use std::time::Duration;
+use tokio::signal::unix::{self, SignalKind};
+use tokio::sync::mpsc;
 use tokio::{fs, signal, time};
 use tracing::subscriber;
 use tracing_subscriber::filter::{EnvFilter, LevelFilter};
@@ -36,21 +48,18 @@ async fn main() -> ExitCode {
     subscriber::set_global_default(registry).unwrap();
 
     let args = Args::parse();
-    let config = match fs::read_to_string(&args.config_path).await {
+    let config = match read_config(&args.config_path).await {
         Ok(config) => config,
         Err(err) => {
-            tracing::error!("Error reading config: {}", err);
+            tracing::error!("{}", err);
             return ExitCode::FAILURE;
         }
     };
 
-    let config = match toml::from_str::<Config>(&config) {
-        Ok(config) => config,
-        Err(err) => {
-            tracing::error!("Error parsing config: {}", err);
-            return ExitCode::FAILURE;
-        }
-    };
+    if config.advertise && !advertise(config.listen.port()) {
+        tracing::error!("Advertising requires rkvm-server to be built with the discovery feature");
+        return ExitCode::FAILURE;
+    }
 
     let acceptor = match tls::configure(&config.certificate, &config.key).await {
         Ok(acceptor) => acceptor,
@@ -67,11 +76,56 @@ async fn main() -> ExitCode {
         }
     };
 
-    let switch_keys = config.switch_keys.into_iter().map(Into::into).collect();
-    let input_device_paths = config.input_device_paths;
+    let devices = config
+        .devices
+        .iter()
+        .map(|device| MatchSpec::parse(device))
+        .collect::<Vec<_>>();
+
+    let mut hangup = match unix::signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            tracing::error!("Error setting up signal handler: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let (reloads_sender, reloads_receiver) = mpsc::channel(1);
+    let reload = async {
+        while hangup.recv().await.is_some() {
+            tracing::info!("Reloading configuration");
+
+            let new = match read_config(&args.config_path).await {
+                Ok(new) => new,
+                Err(err) => {
+                    tracing::error!("{}, keeping the current one", err);
+                    continue;
+                }
+            };
+
+            for name in restart_required(&config, &new) {
+                tracing::warn!("Changing {} requires restart", name);
+            }
+
+            if reloads_sender.send(settings(&new)).await.is_err() {
+                break;
+            }
+        }
+
+        future::pending().await
+    };
+
+    let options = Options {
+        listen: config.listen,
+        acceptor,
+        input_device_paths: &config.input_device_paths,
+        devices: &devices,
+        grabbed: grabbed(&config),
+        control: config.control_socket.as_deref(),
+    };
 
     tokio::select! {
-        result = server::run(config.listen, acceptor, &config.password, &switch_keys, &input_device_paths) => {
+        result = server::run(options, settings(&config), reloads_receiver) => {
             if let Err(err) = result {
                 tracing::error!("Error: {}", err);
                 return ExitCode::FAILURE;
@@ -89,7 +143,153 @@ async fn main() -> ExitCode {
         _ = shutdown => {
             tracing::info!("Shutting down as requested");
         }
+        () = reload => {}
     }
 
     ExitCode::SUCCESS
 }
+
+// Returns false if built without support for it.
+#[cfg(feature = "discovery")]
+fn advertise(port: u16) -> bool {
+    tokio::spawn(async move {
+        // Clients can still connect by address, so this isn't fatal.
+        if let Err(err) = rkvm_net::discovery::advertise(port).await {
+            tracing::error!("Error advertising the server: {}", err);
+        }
+    });
+
+    true
+}
+
+#[cfg(not(feature = "discovery"))]
+fn advertise(_: u16) -> bool {
+    false
+}
+
+async fn read_config(path: &Path) -> Result<Config, String> {
+    let config = fs::read_to_string(path)
+        .await
+        .map_err(|err| format!("Error reading config: {}", err))?;
+
+    toml::from_str(&config).map_err(|err| format!("Error parsing config: {}", err))
+}
+
+fn settings(config: &Config) -> Settings {
+    Settings {
+        password: config.password.clone(),
+        switch_keys: config.switch_keys.iter().copied().map(Into::into).collect(),
+        repress_keys_on_switch: config.repress_keys_on_switch,
+        direct_keys: config
+            .clients
+            .iter()
+            .filter_map(|client| Some((client.key?.into(), Target::Client(client.address))))
+            .chain(config.local_key.map(|key| (key.into(), Target::Local)))
+            .collect(),
+        clients: config.clients.iter().map(|client| client.address).collect(),
+        remaps: config
+            .clients
+            .iter()
+            .filter(|client| !client.remap.is_empty())
+            .map(|client| {
+                let remap = client
+                    .remap
+                    .iter()
+                    .map(|(from, to)| ((*from).into(), (*to).into()))
+                    .collect();
+
+                (client.address, remap)
+            })
+            .collect(),
+        scale_abs_to: config
+            .clients
+            .iter()
+            .filter_map(|client| Some((client.address, client.scale_abs_to?)))
+            .collect(),
+        lock_key: config.lock_key.map(Into::into),
+        layout: layout(config),
+        keepalive: Keepalive {
+            interval: Duration::from_millis(config.ping_interval.get()),
+            missed_pongs: config.missed_pongs.get(),
+        },
+        max_frame_delay: Duration::from_micros(config.max_frame_delay_us),
+        passthrough_local: config.passthrough_local,
+    }
+}
+
+fn grabbed(config: &Config) -> GrabbedPolicy {
+    match (config.fail_on_grabbed, config.grab_retry_interval) {
+        (true, _) => GrabbedPolicy::Fail,
+        (false, 0) => GrabbedPolicy::Skip,
+        (false, interval) => GrabbedPolicy::Retry(Duration::from_secs(interval)),
+    }
+}
+
+fn layout(config: &Config) -> Layout {
+    let mut layout = Layout::new();
+
+    let resolution = match config.resolution {
+        Some(resolution) => resolution,
+        None => return layout,
+    };
+
+    layout.resolution(Target::Local, resolution.map(NonZeroU32::get));
+
+    for client in &config.clients {
+        let target = Target::Client(client.address);
+
+        if let Some(resolution) = client.resolution {
+            layout.resolution(target, resolution.map(NonZeroU32::get));
+        }
+
+        if let Some(position) = client.position {
+            let of = position.of.map_or(Target::Local, Target::Client);
+            layout.neighbor(target, position.edge, of);
+        }
+    }
+
+    layout
+}
+
+// The options that are only read on startup and differ between the two configs.
+fn restart_required(old: &Config, new: &Config) -> Vec<&'static str> {
+    let mut names = Vec::new();
+
+    if old.listen != new.listen {
+        names.push("listen");
+    }
+
+    if old.certificate != new.certificate {
+        names.push("certificate");
+    }
+
+    if old.key != new.key {
+        names.push("key");
+    }
+
+    if old.input_device_paths != new.input_device_paths {
+        names.push("input-device-paths");
+    }
+
+    if old.devices != new.devices {
+        names.push("devices");
+    }
+
+    if old.control_socket != new.control_socket {
+        names.push("control-socket");
+    }
+
+    if old.grab_retry_interval != new.grab_retry_interval {
+        names.push("grab-retry-interval");
+    }
+
+    if old.fail_on_grabbed != new.fail_on_grabbed {
+        names.push("fail-on-grabbed");
+    }
+
+    if old.advertise != new.advertise {
+        names.push("advertise");
+    }
+
+    names
+}
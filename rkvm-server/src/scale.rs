@@ -0,0 +1,171 @@
+use rkvm_input::abs::{AbsAxis, AbsEvent, AbsInfo};
+use rkvm_input::capabilities::DeviceCapabilities;
+use rkvm_input::event::Event;
+use std::collections::HashMap;
+
+// Maps the position axes of a device onto 0 through width - 1 and height - 1, for clients whose screen doesn't
+// match the surface of the device. Other axes, such as pressure or tilt, are left alone.
+pub struct Scale {
+    // The original range of each scaled axis along with its new maximum.
+    axes: HashMap<AbsAxis, (AbsInfo, i32)>,
+}
+
+impl Scale {
+    // Changes the capabilities to the new ranges, None if there is nothing to scale.
+    pub fn new(capabilities: &mut DeviceCapabilities, size: [u32; 2]) -> Option<Self> {
+        let [width, height] = size.map(|size| size.saturating_sub(1).min(i32::MAX as _) as i32);
+        let mut axes = HashMap::new();
+
+        for (axis, info) in &mut capabilities.abs {
+            let max = match axis {
+                AbsAxis::X | AbsAxis::MtPositionX => width,
+                AbsAxis::Y | AbsAxis::MtPositionY => height,
+                _ => continue,
+            };
+
+            if info.max <= info.min {
+                continue;
+            }
+
+            let original = *info;
+            let range = original.max as i64 - original.min as i64;
+            let resize = |value: i32| round(value as i64 * max as i64, range);
+
+            *info = AbsInfo {
+                min: 0,
+                max,
+                fuzz: resize(original.fuzz),
+                flat: resize(original.flat),
+                // Units per millimeter, kept if set so that the device doesn't lose its size altogether.
+                resolution: match original.resolution {
+                    0 => 0,
+                    resolution => resize(resolution).max(1),
+                },
+            };
+
+            axes.insert(*axis, (original, max));
+        }
+
+        if axes.is_empty() {
+            return None;
+        }
+
+        Some(Self { axes })
+    }
+
+    pub fn event(&self, event: Event) -> Event {
+        let (axis, value) = match event {
+            Event::Abs(AbsEvent::Axis { axis, value }) => (axis, value),
+            event => return event,
+        };
+
+        let value = match self.axes.get(&axis) {
+            Some((info, max)) => scale(value, info, *max),
+            None => value,
+        };
+
+        Event::Abs(AbsEvent::Axis { axis, value })
+    }
+}
+
+// Values outside of the original range are clamped, min and max end up exactly at 0 and the new maximum.
+fn scale(value: i32, info: &AbsInfo, max: i32) -> i32 {
+    let value = value.clamp(info.min, info.max) as i64 - info.min as i64;
+    let range = info.max as i64 - info.min as i64;
+
+    round(value * max as i64, range)
+}
+
+// Rounds half up, dividing by a positive number.
+fn round(numerator: i64, denominator: i64) -> i32 {
+    ((2 * numerator + denominator).div_euclid(2 * denominator)) as i32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+    use std::ffi::CString;
+
+    fn info(min: i32, max: i32) -> AbsInfo {
+        AbsInfo {
+            min,
+            max,
+            fuzz: 0,
+            flat: 0,
+            resolution: 0,
+        }
+    }
+
+    fn event(axis: AbsAxis, value: i32) -> Event {
+        Event::Abs(AbsEvent::Axis { axis, value })
+    }
+
+    #[test]
+    fn scaling() {
+        let tablet = info(-100, 15099);
+
+        assert_eq!(scale(-100, &tablet, 1919), 0);
+        assert_eq!(scale(15099, &tablet, 1919), 1919);
+        assert_eq!(scale(7500, &tablet, 1919), 960);
+        assert_eq!(scale(-5000, &tablet, 1919), 0);
+        assert_eq!(scale(i32::MAX, &tablet, 1919), 1919);
+
+        let full = info(i32::MIN, i32::MAX);
+        assert_eq!(scale(i32::MIN, &full, 1079), 0);
+        assert_eq!(scale(i32::MAX, &full, 1079), 1079);
+    }
+
+    #[test]
+    fn capabilities() {
+        let mut capabilities = DeviceCapabilities {
+            name: CString::default(),
+            vendor: 0,
+            product: 0,
+            version: 0,
+            rel: HashSet::new(),
+            abs: [
+                (
+                    AbsAxis::X,
+                    AbsInfo {
+                        resolution: 100,
+                        ..info(0, 15999)
+                    },
+                ),
+                (AbsAxis::Y, info(0, 9999)),
+                (AbsAxis::Pressure, info(0, 8191)),
+            ]
+            .into_iter()
+            .collect(),
+            keys: HashSet::new(),
+            syn: HashSet::new(),
+            msc: HashSet::new(),
+            leds: HashSet::new(),
+            ff: HashSet::new(),
+            switches: HashSet::new(),
+            props: HashSet::new(),
+            repeat: None,
+        };
+
+        let scale = Scale::new(&mut capabilities, [1920, 1080]).unwrap();
+
+        assert_eq!(
+            capabilities.abs[&AbsAxis::X],
+            AbsInfo {
+                resolution: 12,
+                ..info(0, 1919)
+            }
+        );
+        assert_eq!(capabilities.abs[&AbsAxis::Y], info(0, 1079));
+        assert_eq!(capabilities.abs[&AbsAxis::Pressure], info(0, 8191));
+
+        assert_eq!(
+            scale.event(event(AbsAxis::Y, 9999)),
+            event(AbsAxis::Y, 1079)
+        );
+        assert_eq!(
+            scale.event(event(AbsAxis::Pressure, 4000)),
+            event(AbsAxis::Pressure, 4000)
+        );
+    }
+}
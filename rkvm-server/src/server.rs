@@ -1,25 +1,38 @@
-use rkvm_input::abs::{AbsAxis, AbsInfo};
+use crate::chord::Chord;
+use crate::control::{self, Request};
+use crate::frame;
+use crate::layout::Layout;
+use crate::scale::Scale;
+use rkvm_input::capabilities::DeviceCapabilities;
+use rkvm_input::diagnostics::{DeviceInfo, MatchSpec};
 use rkvm_input::event::Event;
-use rkvm_input::key::{Key, KeyEvent};
-use rkvm_input::monitor::Monitor;
-use rkvm_input::rel::RelAxis;
+use rkvm_input::interceptor::GrabMode;
+use rkvm_input::key::{Key, KeyEvent, Keyboard};
+use rkvm_input::monitor::{GrabbedPolicy, Monitor, MonitorEvent};
+use rkvm_input::rel::{RelAxis, RelEvent};
+use rkvm_input::switch::{Switch, SwitchEvent};
 use rkvm_input::sync::SyncEvent;
+use rkvm_input::writer::DeviceRequest;
 use rkvm_net::auth::{AuthChallenge, AuthResponse, AuthStatus};
 use rkvm_net::message::Message;
 use rkvm_net::version::Version;
-use rkvm_net::{Pong, Update};
+use rkvm_net::{Feedback, Update};
 use slab::Slab;
 use std::collections::{HashMap, HashSet, VecDeque};
-use std::ffi::CString;
+use std::fmt::{self, Display, Formatter};
+use std::future;
 use std::io::{self, ErrorKind};
-use std::net::SocketAddr;
-use std::time::Instant;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use tokio::io::{AsyncWriteExt, BufStream};
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufStream};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::mpsc::{self, Receiver, Sender};
-use tokio::time;
+use tokio::sync::watch;
+use tokio::time::{self, MissedTickBehavior};
 use tokio_rustls::TlsAcceptor;
 use tracing::Instrument;
 
@@ -31,36 +44,178 @@ pub enum Error {
     Input(io::Error),
     #[error("Event queue overflow")]
     Overflow,
+    #[error("Control socket error: {0}")]
+    Control(io::Error),
 }
 
+#[derive(Clone, Copy)]
+pub struct Keepalive {
+    pub interval: Duration,
+    // How many pings in a row may go unanswered before the client is dropped.
+    pub missed_pongs: u32,
+}
+
+// What can be changed by reloading the configuration, see `run`.
+#[derive(Clone)]
+pub struct Settings {
+    pub password: String,
+    pub switch_keys: HashSet<Key>,
+    pub repress_keys_on_switch: bool,
+    // Keys switching straight to their target when pressed along with the switch keys. If there are any,
+    // the switch keys alone no longer cycle through the clients.
+    pub direct_keys: HashMap<Key, Target>,
+    // The addresses of the configured clients, connected or not.
+    pub clients: Vec<IpAddr>,
+    // Keys replaced by others just before being sent to the client with the address.
+    pub remaps: HashMap<IpAddr, HashMap<Key, Key>>,
+    // Sizes to rescale absolute pointer positions to for the client with the address, fixed for the duration
+    // of a connection as the devices are created with the ranges.
+    pub scale_abs_to: HashMap<IpAddr, [u32; 2]>,
+    // While locked, the switch keys are just keys and edges of the screens are just edges.
+    pub lock_key: Option<Key>,
+    pub layout: Layout,
+    pub keepalive: Keepalive,
+    // How long complete motion frames may be held back to merge them with the ones following, zero to send
+    // them right away.
+    pub max_frame_delay: Duration,
+    // Release the devices while the server is the target, so that its input doesn't go through the writers.
+    pub passthrough_local: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Target {
+    Local,
+    Client(IpAddr),
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Local => write!(f, "server"),
+            Self::Client(address) => write!(f, "{}", address),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        match data {
+            "server" => Ok(Self::Local),
+            address => address
+                .parse()
+                .map(Self::Client)
+                .map_err(|_| format!("Unknown machine {:?}", data)),
+        }
+    }
+}
+
+// What can't be changed without restarting the server.
+pub struct Options<'a> {
+    pub listen: SocketAddr,
+    pub acceptor: TlsAcceptor,
+    pub input_device_paths: &'a HashSet<String>,
+    pub devices: &'a [MatchSpec],
+    pub grabbed: GrabbedPolicy,
+    pub control: Option<&'a Path>,
+}
+
+// Reloaded settings take effect without touching the devices. A new password disconnects everyone authenticated
+// with the old one and clients removed from the configuration are disconnected as well, the others stay connected
+// and in control. A new keepalive only applies to new connections.
 pub async fn run(
-    listen: SocketAddr,
-    acceptor: TlsAcceptor,
-    password: &str,
-    switch_keys: &HashSet<Key>,
-    input_device_paths: &HashSet<String>,
+    options: Options<'_>,
+    mut settings: Settings,
+    mut reloads: Receiver<Settings>,
 ) -> Result<(), Error> {
+    let Options {
+        listen,
+        acceptor,
+        input_device_paths,
+        devices,
+        grabbed,
+        control,
+    } = options;
+
     let listener = TcpListener::bind(&listen).await.map_err(Error::Network)?;
     tracing::info!("Listening on {}", listen);
 
-    let mut monitor = Monitor::new(input_device_paths);
+    let (control_sender, mut control_receiver) = mpsc::channel(1);
+    if let Some(path) = control {
+        let listener = control::bind(path).await.map_err(Error::Control)?;
+        tracing::info!("Listening for control connections on {}", path.display());
+
+        let control_sender = control_sender.clone();
+        tokio::spawn(async move {
+            if let Err(err) = control::listen(listener, control_sender).await {
+                tracing::error!("Control socket error: {}", err);
+            }
+        });
+    }
+
+    let mut monitor = Monitor::builder()
+        .input_device_paths(input_device_paths)
+        .allow(devices.iter().cloned())
+        .grabbed(grabbed)
+        .build();
     let mut devices = Slab::<Device>::new();
     let mut clients = Slab::<(Sender<_>, SocketAddr)>::new();
     let mut current = 0;
-    let mut previous = 0;
-    let mut changed = false;
-    let mut pressed_keys = HashSet::new();
+    let mut chord = Chord::new(settings.switch_keys.clone());
+    // The target input is locked to.
+    let mut locked = None;
+    // Non switch keys currently held, per device.
+    let mut held_keys = HashMap::<usize, HashSet<Key>>::new();
+    // Direct switch keys held since they were pressed along with the switch keys, along with their device.
+    let mut consumed_keys = HashSet::new();
 
     let (events_sender, mut events_receiver) = mpsc::channel(1);
+    let (requests_sender, mut requests_receiver) = mpsc::channel(1);
+    let (disconnects_sender, mut disconnects_receiver) = mpsc::channel(1);
+    let (remaps_sender, remaps_receiver) = watch::channel(settings.remaps.clone());
 
     loop {
+        // Nothing can switch away from a locked target, so it is gone if it isn't the current one anymore.
+        if matches!(locked, Some(idx) if idx != current) {
+            locked = None;
+            tracing::info!("Locked client disconnected, unlocked");
+        }
+
+        // Keys held through the writers have to be released there first. Whatever is pressed after releasing
+        // reaches the server on its own, but is still read to notice the switch keys.
+        if !settings.passthrough_local {
+            set_grab(&mut devices, true)?;
+        } else if current == 0
+            && chord.is_idle()
+            && consumed_keys.is_empty()
+            && held_keys.values().all(HashSet::is_empty)
+        {
+            set_grab(&mut devices, false)?;
+        }
+
         let event = async { events_receiver.recv().await.unwrap() };
+        let request = async { requests_receiver.recv().await.unwrap() };
+        let disconnect = async { disconnects_receiver.recv().await.unwrap() };
+        let control = async { control_receiver.recv().await.unwrap() };
+        let reload = async {
+            match reloads.recv().await {
+                Some(settings) => settings,
+                None => future::pending().await,
+            }
+        };
 
         tokio::select! {
             result = listener.accept() => {
                 let (stream, addr) = result.map_err(Error::Network)?;
                 let acceptor = acceptor.clone();
-                let password = password.to_owned();
+                let connection = Connection {
+                    password: settings.password.clone(),
+                    keepalive: settings.keepalive,
+                    max_frame_delay: settings.max_frame_delay,
+                    remaps: remaps_receiver.clone(),
+                    scale_abs_to: settings.scale_abs_to.get(&addr.ip()).copied(),
+                };
 
                 // Remove dead clients.
                 clients.retain(|_, (client, _)| !client.is_closed());
@@ -70,73 +225,76 @@ pub async fn run(
 
                 let init_updates = devices
                     .iter()
-                    .map(|(id, device)| Update::CreateDevice {
-                        id,
-                        name: device.name.clone(),
-                        version: device.version,
-                        vendor: device.vendor,
-                        product: device.product,
-                        rel: device.rel.clone(),
-                        abs: device.abs.clone(),
-                        keys: device.keys.clone(),
-                    })
+                    .flat_map(|(id, device)| create_updates(id, device))
                     .collect();
 
                 let (sender, receiver) = mpsc::channel(1);
-                clients.insert((sender, addr));
+                let idx = clients.insert((sender, addr));
+                let requests_sender = requests_sender.clone();
+                let disconnects_sender = disconnects_sender.clone();
 
                 let span = tracing::info_span!("connection", addr = %addr);
                 tokio::spawn(
                     async move {
                         tracing::info!("Connected");
 
-                        match client(init_updates, receiver, stream, acceptor, &password).await {
+                        let result = client(
+                            init_updates,
+                            receiver,
+                            idx,
+                            requests_sender,
+                            stream,
+                            acceptor,
+                            connection,
+                        )
+                        .await;
+
+                        match result {
                             Ok(()) => tracing::info!("Disconnected"),
                             Err(err) => tracing::error!("Disconnected: {}", err),
                         }
+
+                        let _ = disconnects_sender.send(idx).await;
                     }
                     .instrument(span),
                 );
             }
             result = monitor.read() => {
-                let mut interceptor = result.map_err(Error::Input)?;
+                let mut interceptor = match result.map_err(Error::Input)? {
+                    MonitorEvent::Added(interceptor) => interceptor,
+                    // The device is destroyed once its interceptor reports the disconnection,
+                    // until then its events are ignored so that nothing stale gets forwarded.
+                    MonitorEvent::Removed(path) => {
+                        let device = devices.iter_mut().find(|(_, device)| device.path == path);
+                        if let Some((id, device)) = device {
+                            device.removed = true;
+                            held_keys.remove(&id);
 
-                let name = interceptor.name().to_owned();
-                let id = devices.vacant_key();
-                let version = interceptor.version();
-                let vendor = interceptor.vendor();
-                let product = interceptor.product();
-                let rel = interceptor.rel().collect::<HashSet<_>>();
-                let abs = interceptor.abs().collect::<HashMap<_,_>>();
-                let keys = interceptor.key().collect::<HashSet<_>>();
-
-                for (_, (sender, _)) in &clients {
-                    let update = Update::CreateDevice {
-                        id,
-                        name: name.clone(),
-                        version: version.clone(),
-                        vendor: vendor.clone(),
-                        product: product.clone(),
-                        rel: rel.clone(),
-                        abs: abs.clone(),
-                        keys: keys.clone(),
-                    };
+                            tracing::info!(id = %id, name = ?device.info.name, path = ?device.path, "Device removed");
+                        }
 
-                    let _ = sender.send(update).await;
-                }
+                        continue;
+                    }
+                };
 
                 let (interceptor_sender, mut interceptor_receiver) = mpsc::channel(32);
-                devices.insert(Device {
-                    name,
-                    version,
-                    vendor,
-                    product,
-                    rel,
-                    abs,
-                    keys,
+                let id = devices.insert(Device {
+                    path: interceptor.path().to_owned(),
+                    info: interceptor.info().clone(),
+                    exclusive: interceptor.grab_mode() == GrabMode::Exclusive,
+                    released: false,
+                    removed: false,
+                    capabilities: interceptor.capabilities(),
+                    switches: interceptor.snapshot_state().switches,
                     sender: interceptor_sender,
                 });
 
+                for (_, (sender, _)) in &clients {
+                    for update in create_updates(id, &devices[id]) {
+                        let _ = sender.send(update).await;
+                    }
+                }
+
                 let events_sender = events_sender.clone();
                 tokio::spawn(async move {
                     loop {
@@ -146,13 +304,38 @@ pub async fn run(
                                     break;
                                 }
                             }
-                            event = interceptor_receiver.recv() => {
-                                let event = match event {
-                                    Some(event) => event,
+                            command = interceptor_receiver.recv() => {
+                                let command = match command {
+                                    Some(command) => command,
                                     None => break,
                                 };
 
-                                match interceptor.write(&event).await {
+                                // Requests come from the client in control and are meant for the device itself.
+                                let result = match command {
+                                    Command::Write(event) => interceptor.write(&event).await,
+                                    // The device is still usable, its input just goes to the wrong place.
+                                    Command::Grab(grab) => {
+                                        if let Err(err) = interceptor.set_grab(grab) {
+                                            match grab {
+                                                true => tracing::error!(id = %id, "Failed to grab device again, its input keeps reaching the server: {}", err),
+                                                false => tracing::warn!(id = %id, "Failed to release device: {}", err),
+                                            }
+                                        }
+
+                                        Ok(())
+                                    }
+                                    Command::Request(DeviceRequest::Led(event)) => interceptor.set_led(&event),
+                                    // An effect the device can't play only affects whoever asked for it.
+                                    Command::Request(DeviceRequest::Ff(request)) => {
+                                        if let Err(err) = interceptor.apply_ff(&request) {
+                                            tracing::warn!(id = %id, "Failed to apply {:?}: {}", request, err);
+                                        }
+
+                                        Ok(())
+                                    }
+                                };
+
+                                match result {
                                     Ok(()) => {},
                                     Err(err) => {
                                         let _ = events_sender.send((id, Err(err))).await;
@@ -170,95 +353,286 @@ pub async fn run(
 
                 tracing::info!(
                     id = %id,
-                    name = ?device.name,
-                    vendor = %device.vendor,
-                    product = %device.product,
-                    version = %device.version,
+                    name = ?device.info.name,
+                    vendor = %format_args!("{:04x}", device.info.vendor),
+                    product = %format_args!("{:04x}", device.info.product),
+                    version = %format_args!("{:04x}", device.info.version),
+                    bus = %format_args!("{:04x}", device.info.bus_type),
+                    path = ?device.path,
                     "Registered new device"
                 );
             }
+            new = reload => {
+                let password_changed = new.password != settings.password;
+                let closed = clients
+                    .iter()
+                    .filter(|(_, (_, addr))| password_changed || is_removed(&settings.clients, &new.clients, addr.ip()))
+                    .map(|(idx, (_, addr))| (idx, *addr))
+                    .collect::<Vec<_>>();
+
+                for (idx, addr) in closed {
+                    // Dropping the sender closes the connection.
+                    remove_client(&mut clients, &mut current, &mut chord, idx);
+
+                    match password_changed {
+                        true => tracing::info!(addr = %addr, "Password changed, disconnected client"),
+                        false => tracing::info!(addr = %addr, "Client removed from the configuration, disconnected it"),
+                    }
+                }
+
+                if new.switch_keys != settings.switch_keys {
+                    // Whatever is held now counts towards the new combination only once pressed again.
+                    for (id, event) in chord.flush() {
+                        let idx = current;
+                        let events = [Event::Key(event), Event::Sync(SyncEvent::All)];
+
+                        send(&devices, &mut clients, &mut current, idx, id, events).await?;
+                    }
+
+                    chord = Chord::new(new.switch_keys.clone());
+                }
+
+                remaps_sender.send_replace(new.remaps.clone());
+                settings = new;
+                tracing::info!("Reloaded configuration");
+            }
+            (request, reply) = control => {
+                let next = match request {
+                    Request::List => {
+                        let _ = reply.send(Ok(machines(&clients, &settings, current)));
+                        continue;
+                    }
+                    Request::Status => {
+                        let status = vec![
+                            format!("active\t{}", target_of(&clients, current)),
+                            format!("locked\t{}", locked.is_some()),
+                        ];
+
+                        let _ = reply.send(Ok(status));
+                        continue;
+                    }
+                    _ if locked.is_some() => Err("Input is locked".to_owned()),
+                    Request::Switch(name) => match name.parse::<Target>() {
+                        Ok(target) => find_target(&clients, &target)
+                            .ok_or_else(|| format!("{} is not connected", name)),
+                        Err(err) => Err(err),
+                    },
+                    Request::SwitchNext => Ok(next_target(&clients, current)),
+                };
+
+                let next = match next {
+                    Ok(next) => next,
+                    Err(err) => {
+                        let _ = reply.send(Err(err));
+                        continue;
+                    }
+                };
+
+                set_grab(&mut devices, true)?;
+                switch(
+                    &devices,
+                    &mut clients,
+                    &mut current,
+                    &mut chord,
+                    &held_keys,
+                    next,
+                    settings.repress_keys_on_switch,
+                )
+                .await?;
+                let _ = reply.send(Ok(Vec::new()));
+            }
+            idx = disconnect => {
+                // The slot might have been reused by a new client already if the disconnection was noticed
+                // while sending to it.
+                if !matches!(clients.get(idx), Some((sender, _)) if sender.is_closed()) {
+                    continue;
+                }
+
+                remove_client(&mut clients, &mut current, &mut chord, idx);
+            }
+            (idx, id, request) = request => {
+                // Every client has its own LED state and its own effects, only the one in control reaches the devices.
+                if current != idx + 1 {
+                    continue;
+                }
+
+                let device = match devices.get(id) {
+                    Some(device) if !device.removed => device,
+                    _ => continue,
+                };
+
+                let supported = match request {
+                    DeviceRequest::Led(event) => device.capabilities.leds.contains(&event.led),
+                    DeviceRequest::Ff(_) => !device.capabilities.ff.is_empty(),
+                };
+
+                if !supported {
+                    continue;
+                }
+
+                // Not waiting for the same reason as in send().
+                if let Err(TrySendError::Full(_)) = device.sender.try_send(Command::Request(request)) {
+                    tracing::warn!(id = %id, "Dropped {:?}, device is not keeping up", request);
+                }
+            }
             (id, result) = event => match result {
+                Ok(_) if devices[id].removed => {}
                 Ok(event) => {
-                    let mut press = false;
+                    // Where to switch to, if anywhere.
+                    let mut next = None;
+                    // The cursor moved past the edge of the screen, it reaches the new target as well.
+                    let mut crossing = None;
+                    // Key events go through the chord and only reach the current target as it says.
+                    let mut keys = Vec::new();
+                    let mut forward = true;
+
+                    if let Event::Switch(SwitchEvent { switch, value }) = event {
+                        devices[id].switches.insert(switch, value);
+                    }
 
                     if let Event::Key(KeyEvent { key, down }) = event {
-                        if switch_keys.contains(&key) {
-                            press = true;
+                        forward = false;
 
-                            match down {
-                                true => pressed_keys.insert(key),
-                                false => pressed_keys.remove(&key),
-                            };
+                        // Anything that follows could be meant for a client, the key itself already reached the
+                        // server and is released there once it's released.
+                        if down && (chord.keys().contains(&key) || settings.lock_key == Some(key)) {
+                            set_grab(&mut devices, true)?;
                         }
-                    }
 
-                    // Who to send this event to.
-                    let mut idx = current;
+                        let direct = settings.direct_keys.get(&key).filter(|_| locked.is_none());
+                        match direct {
+                            // Neither does the lock key.
+                            _ if down && settings.lock_key == Some(key) => {
+                                consumed_keys.insert((id, key));
 
-                    if press {
-                        if pressed_keys.len() == switch_keys.len() {
-                            let exists = |idx| idx == 0 || clients.contains(idx - 1);
-                            loop {
-                                current = (current + 1) % (clients.len() + 1);
-                                if exists(current) {
-                                    break;
-                                }
+                                locked = match locked {
+                                    Some(_) => {
+                                        tracing::info!("Unlocked");
+                                        None
+                                    }
+                                    None => {
+                                        // The held back switch keys are not going to switch anything now.
+                                        keys = chord.flush();
+
+                                        tracing::info!(idx = %current, "Locked to the current target");
+                                        Some(current)
+                                    }
+                                };
                             }
+                            // The key completing a direct switch reaches nobody, neither does its release.
+                            Some(target) if down && chord.is_complete() => {
+                                consumed_keys.insert((id, key));
+                                chord.complete();
 
-                            previous = idx;
-                            changed = true;
+                                next = find_target(&clients, target);
+                                if next.is_none() {
+                                    tracing::info!(target = ?target, "Not switching, the client is not connected");
+                                }
+                            }
+                            _ if !down && consumed_keys.remove(&(id, key)) => continue,
+                            _ if locked.is_some() => {
+                                if !chord.keys().contains(&key) {
+                                    let held = held_keys.entry(id).or_default();
+                                    match down {
+                                        true => held.insert(key),
+                                        false => held.remove(&key),
+                                    };
+                                }
 
-                            if current != 0 {
-                                tracing::info!(idx = %current, addr = %clients[current - 1].1, "Switched client");
-                            } else {
-                                tracing::info!(idx = %current, "Switched client");
+                                keys = vec![(id, KeyEvent { key, down })];
                             }
-                        } else if changed {
-                            idx = previous;
+                            _ => {
+                                let switch_key = chord.keys().contains(&key);
+                                if !switch_key {
+                                    let held = held_keys.entry(id).or_default();
+                                    match down {
+                                        true => held.insert(key),
+                                        false => held.remove(&key),
+                                    };
+                                }
 
-                            if pressed_keys.is_empty() {
-                                changed = false;
+                                keys = chord.key(id, key, down);
+
+                                // With direct switch keys configured, the switch keys alone do nothing.
+                                if switch_key && down && chord.is_complete() && settings.direct_keys.is_empty() {
+                                    chord.complete();
+                                    next = Some(next_target(&clients, current));
+                                }
                             }
                         }
                     }
 
-                    let events = [event]
-                        .into_iter()
-                        .chain(press.then_some(Event::Sync(SyncEvent::All)));
-
-                    // Index 0 - special case to keep the modular arithmetic above working.
-                    if idx == 0 {
-                        // We do a try_send() here rather than a "blocking" send in order to prevent deadlocks.
-                        // In this scenario, the interceptor task is sending events to the main task,
-                        // while the main task is simultaneously sending events back to the interceptor.
-                        // This creates a classic deadlock situation where both tasks are waiting for each other.
-                        for event in events {
-                            match devices[id].sender.try_send(event) {
-                                Ok(()) | Err(TrySendError::Closed(_)) => {},
-                                Err(TrySendError::Full(_)) => return Err(Error::Overflow),
+                    // Scrolling with the switch keys held is a shortcut as much as pressing a key is.
+                    if let Event::Rel(RelEvent {
+                        axis: RelAxis::Wheel | RelAxis::HWheel | RelAxis::WheelHiRes | RelAxis::HWheelHiRes,
+                        ..
+                    }) = event
+                    {
+                        keys = chord.flush();
+                    }
+
+                    if let (Event::Rel(RelEvent { axis, value }), None, None) = (&event, next, locked) {
+                        let found = settings.layout.motion(target_of(&clients, current), *axis, *value);
+                        if let Some(found) = found {
+                            // Keep pushing against the edge if there is nobody there.
+                            if let Some(target) = find_target(&clients, &found.target) {
+                                settings.layout.enter(&found);
+                                next = Some(target);
+                                crossing = Some(found);
                             }
                         }
+                    }
 
-                        continue;
+                    // All of this is meant for the target before switching.
+                    for (id, event) in keys {
+                        let idx = current;
+                        let events = [Event::Key(event), Event::Sync(SyncEvent::All)];
+
+                        send(&devices, &mut clients, &mut current, idx, id, events).await?;
                     }
 
-                    for event in events {
-                        if clients[idx - 1].0.send(Update::Event { id, event }).await.is_err() {
-                            clients.remove(idx - 1);
+                    if forward {
+                        let idx = current;
+                        let events = [event]
+                            .into_iter()
+                            .chain(crossing.is_some().then_some(Event::Sync(SyncEvent::All)));
 
-                            if current == idx {
-                                current = 0;
-                            }
-                        }
+                        send(&devices, &mut clients, &mut current, idx, id, events).await?;
+                    }
+
+                    if let Some(next) = next {
+                        set_grab(&mut devices, true)?;
+                        switch(
+                            &devices,
+                            &mut clients,
+                            &mut current,
+                            &mut chord,
+                            &held_keys,
+                            next,
+                            settings.repress_keys_on_switch,
+                        )
+                        .await?;
+                    }
+
+                    // The rest of the frame follows.
+                    if let Some(crossing) = crossing {
+                        let event = Event::Rel(RelEvent {
+                            axis: crossing.axis,
+                            value: crossing.value,
+                        });
+
+                        let idx = current;
+                        send(&devices, &mut clients, &mut current, idx, id, [event]).await?;
                     }
                 }
                 Err(err) if err.kind() == ErrorKind::BrokenPipe => {
                     for (_, (sender, _)) in &clients {
                         let _ = sender.send(Update::DestroyDevice { id }).await;
                     }
-                    devices.remove(id);
+                    let device = devices.remove(id);
+                    held_keys.remove(&id);
 
-                    tracing::info!(id = %id, "Destroyed device");
+                    tracing::info!(id = %id, name = ?device.info.name, path = ?device.path, "Destroyed device");
                 }
                 Err(err) => return Err(Error::Input(err)),
             }
@@ -266,22 +640,332 @@ pub async fn run(
     }
 }
 
+// Whether a client connecting from the address was configured before reloading, but no longer is. Clients that
+// were never configured are accepted either way.
+fn is_removed(old: &[IpAddr], new: &[IpAddr], address: IpAddr) -> bool {
+    old.contains(&address) && !new.contains(&address)
+}
+
+// Forgets a client whose connection is gone, or closes it by dropping its sender. Nothing is released on it, but
+// the releases of the switch keys it was sent don't go anywhere else either.
+fn remove_client(
+    clients: &mut Slab<(Sender<Update>, SocketAddr)>,
+    current: &mut usize,
+    chord: &mut Chord,
+    idx: usize,
+) {
+    clients.remove(idx);
+
+    // Don't wait for the next event to find out that it has nowhere to go. A lock to the client goes with it.
+    if *current == idx + 1 {
+        *current = 0;
+        chord.switched();
+
+        tracing::info!(idx = %idx + 1, "Client in control disconnected, switched to local input");
+    }
+}
+
+// The target after the current one, in the order clients connected in.
+fn next_target(clients: &Slab<(Sender<Update>, SocketAddr)>, current: usize) -> usize {
+    let exists = |idx| idx == 0 || clients.contains(idx - 1);
+
+    let mut target = current;
+    loop {
+        target = (target + 1) % (clients.len() + 1);
+        if exists(target) {
+            break target;
+        }
+    }
+}
+
+// The server followed by the configured clients and then by the others that are connected, as listed on the
+// control socket.
+fn machines(
+    clients: &Slab<(Sender<Update>, SocketAddr)>,
+    settings: &Settings,
+    current: usize,
+) -> Vec<String> {
+    let mut targets = vec![Target::Local];
+    let connected = clients
+        .iter()
+        .map(|(_, (_, addr))| Target::Client(addr.ip()));
+
+    for target in settings
+        .clients
+        .iter()
+        .copied()
+        .map(Target::Client)
+        .chain(connected)
+    {
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+
+    let active = target_of(clients, current);
+
+    targets
+        .into_iter()
+        .map(|target| {
+            let connected = match find_target(clients, &target) {
+                Some(_) => "connected",
+                None => "disconnected",
+            };
+
+            let active = match target == active {
+                true => "active",
+                false => "inactive",
+            };
+
+            format!("{}\t{}\t{}", target, connected, active)
+        })
+        .collect()
+}
+
+fn target_of(clients: &Slab<(Sender<Update>, SocketAddr)>, idx: usize) -> Target {
+    match idx {
+        0 => Target::Local,
+        idx => Target::Client(clients[idx - 1].1.ip()),
+    }
+}
+
+// The index of a connected target, in the same format as the current one.
+fn find_target(clients: &Slab<(Sender<Update>, SocketAddr)>, target: &Target) -> Option<usize> {
+    let address = match target {
+        Target::Local => return Some(0),
+        Target::Client(address) => address,
+    };
+
+    clients
+        .iter()
+        .find(|(_, (sender, addr))| addr.ip() == *address && !sender.is_closed())
+        .map(|(idx, _)| idx + 1)
+}
+
+// Switches to the given target, releasing whatever is held on the previous one.
+async fn switch(
+    devices: &Slab<Device>,
+    clients: &mut Slab<(Sender<Update>, SocketAddr)>,
+    current: &mut usize,
+    chord: &mut Chord,
+    held_keys: &HashMap<usize, HashSet<Key>>,
+    next: usize,
+    repress: bool,
+) -> Result<(), Error> {
+    let previous = *current;
+    *current = next;
+
+    if *current != previous {
+        for (id, key) in chord.switched() {
+            let events = [
+                Event::Key(KeyEvent { key, down: false }),
+                Event::Sync(SyncEvent::All),
+            ];
+
+            send(devices, clients, current, previous, id, events).await?;
+        }
+
+        switch_to(devices, clients, current, held_keys, previous, repress).await?;
+    }
+
+    if *current != 0 {
+        tracing::info!(idx = %current, addr = %clients[*current - 1].1, "Switched client");
+    } else {
+        tracing::info!(idx = %current, "Switched client");
+    }
+
+    Ok(())
+}
+
+// Sends events of a device to the given target, removing the client if it disconnected.
+async fn send<T: IntoIterator<Item = Event>>(
+    devices: &Slab<Device>,
+    clients: &mut Slab<(Sender<Update>, SocketAddr)>,
+    current: &mut usize,
+    idx: usize,
+    id: usize,
+    events: T,
+) -> Result<(), Error> {
+    // Index 0 - special case to keep the modular arithmetic above working.
+    if idx == 0 {
+        // We do a try_send() here rather than a "blocking" send in order to prevent deadlocks.
+        // In this scenario, the interceptor task is sending events to the main task,
+        // while the main task is simultaneously sending events back to the interceptor.
+        // This creates a classic deadlock situation where both tasks are waiting for each other.
+        for event in events {
+            match devices[id].sender.try_send(Command::Write(event)) {
+                Ok(()) | Err(TrySendError::Closed(_)) => {}
+                Err(TrySendError::Full(_)) => return Err(Error::Overflow),
+            }
+        }
+
+        return Ok(());
+    }
+
+    for event in events {
+        let sender = match clients.get(idx - 1) {
+            Some((sender, _)) => sender,
+            None => break,
+        };
+
+        if sender.send(Update::Event { id, event }).await.is_err() {
+            clients.remove(idx - 1);
+
+            if *current == idx {
+                *current = 0;
+            }
+
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// Releases the keys held on the previous target so that they don't get stuck there,
+// and optionally presses them on the current one. Modifiers are released last and pressed first,
+// so that neither target sees a plain key where a shortcut was intended.
+async fn switch_to(
+    devices: &Slab<Device>,
+    clients: &mut Slab<(Sender<Update>, SocketAddr)>,
+    current: &mut usize,
+    held_keys: &HashMap<usize, HashSet<Key>>,
+    previous: usize,
+    repress: bool,
+) -> Result<(), Error> {
+    for (id, keys) in held_keys {
+        if keys.is_empty() {
+            continue;
+        }
+
+        let mut keys = keys.iter().copied().collect::<Vec<_>>();
+        keys.sort_by_key(is_modifier);
+
+        let release = keys
+            .iter()
+            .map(|key| {
+                Event::Key(KeyEvent {
+                    key: *key,
+                    down: false,
+                })
+            })
+            .chain([Event::Sync(SyncEvent::All)]);
+
+        send(devices, clients, current, previous, *id, release).await?;
+
+        if repress {
+            let idx = *current;
+            let press = keys
+                .iter()
+                .rev()
+                .map(|key| {
+                    Event::Key(KeyEvent {
+                        key: *key,
+                        down: true,
+                    })
+                })
+                .chain([Event::Sync(SyncEvent::All)]);
+
+            send(devices, clients, current, idx, *id, press).await?;
+        }
+    }
+
+    Ok(())
+}
+
+fn is_modifier(key: &Key) -> bool {
+    matches!(
+        key,
+        Key::Key(
+            Keyboard::LeftCtrl
+                | Keyboard::RightCtrl
+                | Keyboard::LeftShift
+                | Keyboard::RightShift
+                | Keyboard::LeftAlt
+                | Keyboard::RightAlt
+                | Keyboard::LeftMeta
+                | Keyboard::RightMeta
+        )
+    )
+}
+
 struct Device {
-    name: CString,
-    vendor: u16,
-    product: u16,
-    version: u16,
-    rel: HashSet<RelAxis>,
-    abs: HashMap<AbsAxis, AbsInfo>,
-    keys: HashSet<Key>,
-    sender: Sender<Event>,
+    path: PathBuf,
+    // As the device was opened, before the interceptor changed anything about it.
+    info: DeviceInfo,
+    // Whether the device was grabbed when opened, only such devices can be released.
+    exclusive: bool,
+    released: bool,
+    removed: bool,
+    capabilities: DeviceCapabilities,
+    // As last reported by the device, clients creating it later only learn about it this way.
+    switches: HashMap<Switch, bool>,
+    sender: Sender<Command>,
+}
+
+// Grabs the devices released while the server is the target, or releases them. Not waiting for the same reason
+// as in send(), the tasks handle the commands in order with the writes.
+fn set_grab(devices: &mut Slab<Device>, grab: bool) -> Result<(), Error> {
+    for (_, device) in devices {
+        if !device.exclusive || device.released != grab {
+            continue;
+        }
+
+        match device.sender.try_send(Command::Grab(grab)) {
+            Ok(()) | Err(TrySendError::Closed(_)) => {}
+            Err(TrySendError::Full(_)) => return Err(Error::Overflow),
+        }
+
+        device.released = !grab;
+    }
+
+    Ok(())
+}
+
+// The updates creating the device on a client. A new device has all of its switches off,
+// so the ones that are on are reported right away.
+fn create_updates(id: usize, device: &Device) -> Vec<Update> {
+    let mut updates = vec![Update::CreateDevice {
+        id,
+        capabilities: device.capabilities.clone(),
+    }];
+
+    let switches = device
+        .switches
+        .iter()
+        .filter(|(_, value)| **value)
+        .map(|(switch, value)| Update::Event {
+            id,
+            event: Event::Switch(SwitchEvent {
+                switch: *switch,
+                value: *value,
+            }),
+        })
+        .collect::<Vec<_>>();
+
+    if !switches.is_empty() {
+        updates.extend(switches);
+        updates.push(Update::Event {
+            id,
+            event: Event::Sync(SyncEvent::All),
+        });
+    }
+
+    updates
+}
+
+// What the task of a device does on its behalf.
+enum Command {
+    Write(Event),
+    Request(DeviceRequest),
+    Grab(bool),
 }
 
 #[derive(Error, Debug)]
 enum ClientError {
     #[error(transparent)]
     Io(#[from] io::Error),
-    #[error("Incompatible client version (got {client}, expected {server})")]
+    #[error("Incompatible client version, server speaks {server}, client speaks {client}")]
     Version { server: Version, client: Version },
     #[error("Invalid password")]
     Auth,
@@ -289,13 +973,36 @@ enum ClientError {
     Rand(#[from] rand::Error),
 }
 
+// The settings a connection is made with, reloading only changes the remaps of the ones already made.
+struct Connection {
+    password: String,
+    keepalive: Keepalive,
+    max_frame_delay: Duration,
+    remaps: watch::Receiver<HashMap<IpAddr, HashMap<Key, Key>>>,
+    scale_abs_to: Option<[u32; 2]>,
+}
+
 async fn client(
     mut init_updates: VecDeque<Update>,
     mut receiver: Receiver<Update>,
+    idx: usize,
+    requests: Sender<(usize, usize, DeviceRequest)>,
     stream: TcpStream,
     acceptor: TlsAcceptor,
-    password: &str,
+    connection: Connection,
 ) -> Result<(), ClientError> {
+    let Connection {
+        password,
+        keepalive,
+        max_frame_delay,
+        remaps,
+        scale_abs_to,
+    } = connection;
+
+    let address = stream.peer_addr()?.ip();
+    // Input is all small writes, none of which should wait for more to come.
+    stream.set_nodelay(true)?;
+
     let stream = rkvm_net::timeout(rkvm_net::TLS_TIMEOUT, acceptor.accept(stream)).await?;
     tracing::info!("TLS connected");
 
@@ -329,7 +1036,7 @@ async fn client(
 
     let response =
         rkvm_net::timeout(rkvm_net::READ_TIMEOUT, AuthResponse::decode(&mut stream)).await?;
-    let status = match response.verify(&challenge, password) {
+    let status = match response.verify(&challenge, &password) {
         true => AuthStatus::Passed,
         false => AuthStatus::Failed,
     };
@@ -348,53 +1055,302 @@ async fn client(
 
     tracing::info!("Authenticated successfully");
 
-    let mut interval = time::interval(rkvm_net::PING_INTERVAL);
+    let (mut reader, mut stream) = tokio::io::split(stream);
+    let (pong_sender, mut pong_receiver) = mpsc::channel(1);
 
-    loop {
-        let recv = async {
-            match init_updates.pop_front() {
-                Some(update) => Some(update),
-                None => receiver.recv().await,
+    // The client sends LED changes and force feedback requests whenever they happen, so it has to be read from
+    // all the time rather than only after pings. Decoding isn't cancel safe, which rules out selecting on it.
+    let read = async {
+        loop {
+            match Feedback::decode(&mut reader).await? {
+                Feedback::Pong => {
+                    if pong_sender.send(()).await.is_err() {
+                        break;
+                    }
+                }
+                Feedback::Led { id, event } => {
+                    tracing::debug!(id = %id, led = ?event.led, state = %event.state, "Received LED change");
+
+                    if requests
+                        .send((idx, id, DeviceRequest::Led(event)))
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+                Feedback::Ff { id, request } => {
+                    tracing::trace!(id = %id, request = ?request, "Received force feedback request");
+
+                    if requests
+                        .send((idx, id, DeviceRequest::Ff(request)))
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
             }
-        };
+        }
 
-        let update = tokio::select! {
-            // Make sure pings have priority.
-            // The client could time out otherwise.
-            biased;
+        Ok(())
+    };
 
-            _ = interval.tick() => Some(Update::Ping),
-            recv = recv => recv,
-        };
+    let write = async {
+        let mut interval = time::interval(keepalive.interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
-        let update = match update {
-            Some(update) => update,
-            None => break,
-        };
+        // Pings sent since the last pong.
+        let mut missed = 0;
+        let mut ping_sent = Instant::now();
+        // Events of each device since its last SYN_REPORT, frames go out as a whole once complete.
+        let mut frames = HashMap::<_, Vec<_>>::new();
+        // A complete motion frame waiting for more to merge with, until the deadline.
+        let mut held: Option<(usize, Vec<Event>)> = None;
+        let mut held_until = time::Instant::now();
+        // What held keys were sent as, so that releases match their presses even if the remapping changed since.
+        let mut sent_keys = HashMap::new();
+        let mut scales = HashMap::new();
 
-        let start = Instant::now();
-        rkvm_net::timeout(rkvm_net::WRITE_TIMEOUT, async {
-            update.encode(&mut stream).await?;
-            stream.flush().await?;
+        loop {
+            let recv = async {
+                match init_updates.pop_front() {
+                    Some(update) => Some(update),
+                    None => receiver.recv().await,
+                }
+            };
 
-            Ok(())
-        })
-        .await?;
-        let duration = start.elapsed();
+            let update = tokio::select! {
+                // Make sure pings have priority.
+                // The client could time out otherwise.
+                biased;
+
+                _ = interval.tick() => {
+                    if missed >= keepalive.missed_pongs {
+                        return Err(io::Error::new(
+                            ErrorKind::TimedOut,
+                            format!("No pong for the last {} pings", missed),
+                        )
+                        .into());
+                    }
+
+                    missed += 1;
+
+                    // Some slack, pings can be held back by a slow write.
+                    Some(Update::Ping {
+                        timeout: keepalive.interval * keepalive.missed_pongs + rkvm_net::WRITE_TIMEOUT,
+                    })
+                }
+                pong = pong_receiver.recv() => {
+                    pong.ok_or_else(|| io::Error::new(ErrorKind::BrokenPipe, "Reader exited"))?;
+                    missed = 0;
+
+                    // Keeping these as debug because it's not as frequent as other updates.
+                    tracing::debug!(duration = ?ping_sent.elapsed(), "Received pong");
+                    continue;
+                }
+                _ = time::sleep_until(held_until), if held.is_some() => {
+                    held.take().map(|(id, events)| Update::Frame { id, events })
+                }
+                recv = recv => recv,
+            };
+
+            let update = match update {
+                Some(update) => update,
+                None => break,
+            };
+
+            let update = match update {
+                Update::Event {
+                    id,
+                    event: Event::Key(KeyEvent { key, down }),
+                } => {
+                    let remapped = || {
+                        remaps
+                            .borrow()
+                            .get(&address)
+                            .and_then(|remap| remap.get(&key))
+                            .copied()
+                            .unwrap_or(key)
+                    };
+
+                    let sent = match down {
+                        true => *sent_keys.entry((id, key)).or_insert_with(remapped),
+                        false => sent_keys.remove(&(id, key)).unwrap_or_else(remapped),
+                    };
+
+                    Update::Event {
+                        id,
+                        event: Event::Key(KeyEvent { key: sent, down }),
+                    }
+                }
+                Update::CreateDevice {
+                    id,
+                    mut capabilities,
+                } => {
+                    let scale = scale_abs_to.and_then(|size| Scale::new(&mut capabilities, size));
+                    if let Some(scale) = scale {
+                        scales.insert(id, scale);
+                    }
+
+                    Update::CreateDevice { id, capabilities }
+                }
+                Update::Event {
+                    id,
+                    event: event @ Event::Abs(_),
+                } => match scales.get(&id) {
+                    Some(scale) => Update::Event {
+                        id,
+                        event: scale.event(event),
+                    },
+                    None => Update::Event { id, event },
+                },
+                Update::DestroyDevice { id } => {
+                    sent_keys.retain(|(device, _), _| *device != id);
+                    scales.remove(&id);
+                    Update::DestroyDevice { id }
+                }
+                update => update,
+            };
+
+            let update = match update {
+                Update::Event { id, event } => {
+                    let complete = event == Event::Sync(SyncEvent::All);
+                    let frame = frames.entry(id).or_default();
+                    frame.push(event);
 
-        if let Update::Ping = update {
-            // Keeping these as debug because it's not as frequent as other updates.
-            tracing::debug!(duration = ?duration, "Sent ping");
+                    // A device not sending SYN_REPORTs still gets its events through, in parts small enough to
+                    // fit in a message.
+                    if !complete && frame.len() < frame::MAX_EVENTS {
+                        continue;
+                    }
 
-            let start = Instant::now();
-            rkvm_net::timeout(rkvm_net::READ_TIMEOUT, Pong::decode(&mut stream)).await?;
-            let duration = start.elapsed();
+                    let events = frame::coalesce(frames.remove(&id).unwrap_or_default());
 
-            tracing::debug!(duration = ?duration, "Received pong");
+                    if let Some((held_id, held_events)) = &mut held {
+                        if *held_id == id && frame::is_motion(&events) {
+                            frame::merge(held_events, &events);
+                            continue;
+                        }
+                    }
+
+                    // Whatever came after the held frame must not overtake it.
+                    if let Some((id, events)) = held.take() {
+                        write_update(&mut stream, &Update::Frame { id, events }).await?;
+                    }
+
+                    if !max_frame_delay.is_zero() && frame::is_motion(&events) {
+                        held = Some((id, events));
+                        held_until = time::Instant::now() + max_frame_delay;
+                        continue;
+                    }
+
+                    Update::Frame { id, events }
+                }
+                Update::DestroyDevice { id } => {
+                    frames.remove(&id);
+                    if held.as_ref().map(|(held_id, _)| *held_id) == Some(id) {
+                        held = None;
+                    }
+
+                    Update::DestroyDevice { id }
+                }
+                update => update,
+            };
+
+            let duration = write_update(&mut stream, &update).await?;
+            if let Update::Ping { .. } = update {
+                ping_sent = Instant::now();
+                tracing::debug!(duration = ?duration, "Sent ping");
+            }
+
+            tracing::trace!("Wrote an update");
         }
 
-        tracing::trace!("Wrote an update");
+        Ok(())
+    };
+
+    tokio::select! {
+        result = read => result,
+        result = write => result,
     }
+}
 
-    Ok(())
+// Returns how long writing the update took.
+async fn write_update<W: AsyncWrite + Send + Unpin>(
+    stream: &mut W,
+    update: &Update,
+) -> Result<Duration, io::Error> {
+    let start = Instant::now();
+
+    rkvm_net::timeout(rkvm_net::WRITE_TIMEOUT, async {
+        update.encode(stream).await?;
+        stream.flush().await?;
+
+        Ok(())
+    })
+    .await?;
+
+    Ok(start.elapsed())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn address(last: u8) -> IpAddr {
+        IpAddr::from([10, 0, 0, last])
+    }
+
+    #[tokio::test]
+    async fn version_mismatch() {
+        let client = Version::decode(&mut b"rkvm\x02\x00".as_slice())
+            .await
+            .unwrap();
+        let err = ClientError::Version {
+            server: Version::CURRENT,
+            client,
+        };
+
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Incompatible client version, server speaks {}, client speaks v2",
+                Version::CURRENT
+            )
+        );
+    }
+
+    #[test]
+    fn removed() {
+        let old = [address(2), address(3)];
+        let new = [address(3), address(4)];
+
+        assert!(is_removed(&old, &new, address(2)));
+        assert!(!is_removed(&old, &new, address(3)));
+        assert!(!is_removed(&old, &new, address(4)));
+        // Never configured, so it didn't go anywhere.
+        assert!(!is_removed(&old, &new, address(5)));
+    }
+
+    #[test]
+    fn removing_clients() {
+        let mut clients = Slab::new();
+        for last in [2, 3] {
+            let (sender, _) = mpsc::channel(1);
+            clients.insert((sender, SocketAddr::new(address(last), 5258)));
+        }
+
+        let mut chord = Chord::new(HashSet::new());
+
+        // The client in control stays in control, under the same index.
+        let mut current = 2;
+        remove_client(&mut clients, &mut current, &mut chord, 0);
+        assert_eq!(current, 2);
+        assert_eq!(target_of(&clients, current), Target::Client(address(3)));
+
+        remove_client(&mut clients, &mut current, &mut chord, 1);
+        assert_eq!(current, 0);
+        assert!(clients.is_empty());
+    }
 }